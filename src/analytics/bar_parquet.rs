@@ -0,0 +1,265 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use chrono::{
+    NaiveDate,
+    NaiveDateTime,
+};
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::{
+    ByteArray,
+    FixedLenByteArray,
+};
+use parquet::errors::{
+    ParquetError,
+    Result,
+};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+
+use crate::Bar;
+
+/// columns of `Bar::CSV_HEADER`. `notional` is a 128 bit integer
+const SCHEMA: &str = "
+message bar {
+    REQUIRED INT64 order_book_id;
+    REQUIRED INT32 session_date (DATE);
+    REQUIRED BYTE_ARRAY session (UTF8);
+    REQUIRED INT64 open_time (TIMESTAMP(NANOS,false));
+    REQUIRED INT64 close_time (TIMESTAMP(NANOS,false));
+    REQUIRED INT64 open;
+    REQUIRED INT64 high;
+    REQUIRED INT64 low;
+    REQUIRED INT64 close;
+    REQUIRED INT64 volume;
+    REQUIRED FIXED_LEN_BYTE_ARRAY (16) notional (DECIMAL(38,0));
+    REQUIRED DOUBLE vwap;
+    REQUIRED INT64 trade_count;
+    REQUIRED INT64 buy_volume;
+    REQUIRED INT64 sell_volume;
+    OPTIONAL INT64 best_bid_price;
+    OPTIONAL INT64 best_bid_qty;
+    OPTIONAL INT64 best_ask_price;
+    OPTIONAL INT64 best_ask_qty;
+}
+";
+
+/// Writes bars as a Parquet file, with the columns of `Bar::CSV_HEADER`. Needs the `parquet` feature.
+///
+/// Bars are buffered and written as a row group every `row_group_size` bars and on `flush`.
+/// The file is complete once `into_inner` returned.
+/// The first error is kept in `error` and the following bars are not written.
+pub struct BarParquetWriter<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    rows: Vec<Bar>,
+    pub row_group_size: usize,
+    pub error: Option<ParquetError>,
+}
+
+impl<W: Write + Send> BarParquetWriter<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let properties = Arc::new(WriterProperties::builder().build());
+        Ok(Self {
+            writer: SerializedFileWriter::new(writer, schema, properties)?,
+            rows: vec![],
+            row_group_size: 65536,
+            error: None,
+        })
+    }
+
+    pub fn write(&mut self, bar: &Bar) {
+        if self.error.is_some() {
+            return;
+        }
+        self.rows.push(bar.clone());
+        if self.rows.len() >= self.row_group_size {
+            if let Err(e) = self.write_row_group() {
+                self.error.replace(e);
+            }
+        }
+    }
+
+    /// writes the buffered bars as a row group, or returns the error kept in `error`
+    pub fn flush(&mut self) -> Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.write_row_group(),
+        }
+    }
+
+    /// flushes and writes the footer of the file
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush()?;
+        self.writer.into_inner()
+    }
+
+    fn write_row_group(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.rows);
+        let mut row_group = self.writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            write_column(column.untyped(), index, &rows)?;
+            column.close()?;
+            index += 1;
+        }
+        row_group.close()?;
+        Ok(())
+    }
+}
+
+fn write_column(column: &mut ColumnWriter, index: usize, rows: &[Bar]) -> Result<()> {
+    fn int64(column: &mut ColumnWriter, values: Vec<i64>) -> Result<()> {
+        match column {
+            ColumnWriter::Int64ColumnWriter(w) => w.write_batch(&values, None, None).map(|_| ()),
+            _ => Err(ParquetError::General("expected an INT64 column".into())),
+        }
+    }
+    fn optional_int64(column: &mut ColumnWriter, values: Vec<Option<i64>>) -> Result<()> {
+        let def_levels: Vec<i16> = values.iter().map(|v| v.is_some() as i16).collect();
+        let values: Vec<i64> = values.into_iter().flatten().collect();
+        match column {
+            ColumnWriter::Int64ColumnWriter(w) => {
+                w.write_batch(&values, Some(&def_levels), None).map(|_| ())
+            }
+            _ => Err(ParquetError::General("expected an INT64 column".into())),
+        }
+    }
+    fn nanos(t: &NaiveDateTime) -> i64 {
+        t.and_utc().timestamp_nanos_opt().unwrap_or(i64::MAX)
+    }
+
+    let col = |f: fn(&Bar) -> i64| rows.iter().map(f).collect::<Vec<_>>();
+    let opt = |f: fn(&Bar) -> Option<i64>| rows.iter().map(f).collect::<Vec<_>>();
+    match index {
+        0 => int64(column, col(|b| b.order_book_id)),
+        1 => {
+            match column {
+                ColumnWriter::Int32ColumnWriter(w) => {
+                    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+                    let days: Vec<i32> = rows
+                        .iter()
+                        .map(|b| (b.session.date - epoch).num_days() as i32)
+                        .collect();
+                    w.write_batch(&days, None, None).map(|_| ())
+                }
+                _ => Err(ParquetError::General("expected an INT32 column".into())),
+            }
+        }
+        2 => {
+            match column {
+                ColumnWriter::ByteArrayColumnWriter(w) => {
+                    let sessions: Vec<ByteArray> = rows
+                        .iter()
+                        .map(|b| format!("{:?}", b.session.session).as_str().into())
+                        .collect();
+                    w.write_batch(&sessions, None, None).map(|_| ())
+                }
+                _ => Err(ParquetError::General("expected a BYTE_ARRAY column".into())),
+            }
+        }
+        3 => int64(column, rows.iter().map(|b| nanos(&b.open_time)).collect()),
+        4 => int64(column, rows.iter().map(|b| nanos(&b.close_time)).collect()),
+        5 => int64(column, col(|b| b.open)),
+        6 => int64(column, col(|b| b.high)),
+        7 => int64(column, col(|b| b.low)),
+        8 => int64(column, col(|b| b.close)),
+        9 => int64(column, col(|b| b.volume)),
+        10 => {
+            match column {
+                ColumnWriter::FixedLenByteArrayColumnWriter(w) => {
+                    let notional: Vec<FixedLenByteArray> = rows
+                        .iter()
+                        .map(|b| ByteArray::from(b.notional.to_be_bytes().to_vec()).into())
+                        .collect();
+                    w.write_batch(&notional, None, None).map(|_| ())
+                }
+                _ => {
+                    Err(ParquetError::General(
+                        "expected a FIXED_LEN_BYTE_ARRAY column".into(),
+                    ))
+                }
+            }
+        }
+        11 => {
+            match column {
+                ColumnWriter::DoubleColumnWriter(w) => {
+                    let vwap: Vec<f64> = rows.iter().map(|b| b.vwap).collect();
+                    w.write_batch(&vwap, None, None).map(|_| ())
+                }
+                _ => Err(ParquetError::General("expected a DOUBLE column".into())),
+            }
+        }
+        12 => int64(column, col(|b| b.trade_count as i64)),
+        13 => int64(column, col(|b| b.buy_volume)),
+        14 => int64(column, col(|b| b.sell_volume)),
+        15 => optional_int64(column, opt(|b| b.best_bid.map(|l| l.price))),
+        16 => optional_int64(column, opt(|b| b.best_bid.map(|l| l.qty))),
+        17 => optional_int64(column, opt(|b| b.best_ask.map(|l| l.price))),
+        18 => optional_int64(column, opt(|b| b.best_ask.map(|l| l.qty))),
+        _ => Err(ParquetError::General(format!("unexpected column {index}"))),
+    }
+}
+
+#[test]
+fn test_bar_parquet_writer() {
+    use parquet::file::reader::{
+        FileReader,
+        SerializedFileReader,
+    };
+    use parquet::record::RowAccessor;
+
+    use crate::{
+        replay,
+        BarBuilder,
+        BarSpec,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),1,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),2,FUT_NK225M_2109(590334),B,1,5,289950000,0,2",
+        "E,2021-03-01T00:00:10.000000000(1614556810000000000),1,FUT_NK225M_2109(590334),S,2,100,0,,",
+        "E,2021-03-01T00:00:20.000000000(1614556820000000000),2,FUT_NK225M_2109(590334),B,1,101,0,,",
+        "E,2021-03-01T00:00:30.000000000(1614556830000000000),1,FUT_NK225M_2109(590334),S,3,102,0,,",
+    ];
+    let mut bars = vec![];
+    replay(
+        &file,
+        &mut BarBuilder::new(BarSpec::Tick(2), |bar: &Bar| bars.push(bar.clone())),
+    );
+    assert_eq!(bars.len(), 2);
+
+    let path = std::env::temp_dir().join(format!("bars_{}.parquet", std::process::id()));
+    let mut sink = BarParquetWriter::new(std::fs::File::create(&path).unwrap()).unwrap();
+    sink.row_group_size = 1;
+    for bar in bars.iter() {
+        sink.write(bar);
+    }
+    sink.into_inner().unwrap();
+
+    let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(reader.metadata().num_row_groups(), 2);
+    let rows: Vec<_> = reader
+        .get_row_iter(None)
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].get_long(0).unwrap(), 590334);
+    assert_eq!(rows[0].get_string(2).unwrap(), "Day");
+    assert_eq!(rows[0].get_long(5).unwrap(), bars[0].open);
+    assert_eq!(rows[0].get_long(9).unwrap(), bars[0].volume);
+    assert_eq!(rows[0].get_double(11).unwrap(), bars[0].vwap);
+    assert_eq!(
+        rows[1].get_long(15).unwrap(),
+        bars[1].best_bid.unwrap().price
+    );
+    assert!(rows[1].get_long(17).is_err());
+}
@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+use std::io::{
+    self,
+    Write,
+};
+
+use chrono::{
+    Duration,
+    NaiveDateTime,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::callback_datatype::{
+    AggressorSide,
+    Trade,
+};
+use crate::{
+    interval_end,
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    PriceLevelView,
+    ProductFilter,
+    SessionId,
+};
+
+/// when a bar is closed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarSpec {
+    /// fixed intervals aligned to unix epoch (UTC). Intervals without trades produce no bar.
+    Time(Duration),
+    /// every n trades
+    Tick(usize),
+    /// once the volume reaches n. The trade that crosses the threshold is not split.
+    Volume(i64),
+    /// once the notional (price * quantity, in raw price units) reaches n.
+    /// The trade that crosses the threshold is not split.
+    Notional(i128),
+}
+
+/// OHLCV bar of an order book.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bar {
+    pub order_book_id: i64,
+    pub session: SessionId,
+    /// timestamp of the first trade
+    pub open_time: NaiveDateTime,
+    /// timestamp of the last trade
+    pub close_time: NaiveDateTime,
+    pub open: i64,
+    pub high: i64,
+    pub low: i64,
+    pub close: i64,
+    pub volume: i64,
+    pub notional: i128,
+    /// notional / volume
+    pub vwap: f64,
+    pub trade_count: usize,
+    /// volume of buy initiated trades
+    pub buy_volume: i64,
+    /// volume of sell initiated trades
+    pub sell_volume: i64,
+    /// best bid and ask when the bar was closed
+    pub best_bid: Option<PriceLevelView>,
+    pub best_ask: Option<PriceLevelView>,
+}
+
+impl Bar {
+    pub const CSV_HEADER: &'static str = "order_book_id,session_date,session,open_time,close_time,open,high,low,close,volume,notional,vwap,trade_count,buy_volume,sell_volume,best_bid_price,best_bid_qty,best_ask_price,best_ask_qty";
+
+    fn new(trade: &Trade, session: SessionId) -> Self {
+        let mut bar = Bar {
+            order_book_id: trade.order_book_id,
+            session,
+            open_time: trade.timestamp,
+            close_time: trade.timestamp,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: 0,
+            notional: 0,
+            vwap: 0.0,
+            trade_count: 0,
+            buy_volume: 0,
+            sell_volume: 0,
+            best_bid: None,
+            best_ask: None,
+        };
+        bar.update(trade);
+        bar
+    }
+
+    fn update(&mut self, trade: &Trade) {
+        self.close_time = trade.timestamp;
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.quantity;
+        self.notional += trade.notional();
+        self.trade_count += 1;
+        match trade.aggressor_side {
+            AggressorSide::Buy => self.buy_volume += trade.quantity,
+            AggressorSide::Sell => self.sell_volume += trade.quantity,
+            AggressorSide::Auction | AggressorSide::Unknown => (),
+        }
+        if self.volume != 0 {
+            self.vwap = self.notional as f64 / self.volume as f64;
+        }
+    }
+
+    fn is_complete(&self, spec: &BarSpec) -> bool {
+        match *spec {
+            BarSpec::Time(_) => false,
+            BarSpec::Tick(n) => self.trade_count >= n,
+            BarSpec::Volume(n) => self.volume >= n,
+            BarSpec::Notional(n) => self.notional >= n,
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        fn opt(v: Option<i64>) -> String {
+            v.map(|v| v.to_string()).unwrap_or_default()
+        }
+        format!(
+            "{},{},{:?},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.order_book_id,
+            self.session.date,
+            self.session.session,
+            self.open_time.format("%Y-%m-%dT%H:%M:%S%.9f"),
+            self.close_time.format("%Y-%m-%dT%H:%M:%S%.9f"),
+            self.open,
+            self.high,
+            self.low,
+            self.close,
+            self.volume,
+            self.notional,
+            self.vwap,
+            self.trade_count,
+            self.buy_volume,
+            self.sell_volume,
+            opt(self.best_bid.map(|v| v.price)),
+            opt(self.best_bid.map(|v| v.qty)),
+            opt(self.best_ask.map(|v| v.price)),
+            opt(self.best_ask.map(|v| v.qty)),
+        )
+    }
+}
+
+/// Writes bars as CSV, with `Bar::CSV_HEADER` before the first one.
+///
+/// The first io error is kept in `error` and the following bars are not written.
+pub struct BarCsvWriter<W: Write> {
+    writer: W,
+    pub error: Option<io::Error>,
+    header_written: bool,
+}
+
+impl<W: Write> BarCsvWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+            header_written: false,
+        }
+    }
+
+    pub fn write(&mut self, bar: &Bar) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.write_row(bar) {
+            self.error.replace(e);
+        }
+    }
+
+    /// flushes the writer, or returns the error kept in `error`
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_row(&mut self, bar: &Bar) -> io::Result<()> {
+        if !self.header_written {
+            writeln!(self.writer, "{}", Bar::CSV_HEADER)?;
+            self.header_written = true;
+        }
+        writeln!(self.writer, "{}", bar.to_csv())
+    }
+}
+
+struct OpenBar {
+    bar: Bar,
+    /// end of the interval for time bars
+    end: Option<NaiveDateTime>,
+}
+
+/// Builds bars per order book from reconstructed trades, and passes closed bars to `f`.
+///
+/// ```ignore
+/// let file = std::io::BufWriter::new(std::fs::File::create("bars.csv")?);
+/// let mut sink = BarCsvWriter::new(file);
+/// let mut builder = BarBuilder::new(BarSpec::Time(Duration::minutes(1)), |bar: &Bar| sink.write(bar));
+/// order_book_runtime(&mut map, itch.into_iter(), &mut builder);
+/// drop(builder);
+/// sink.flush()?;
+/// ```
+///
+/// Time bars are closed as soon as the runtime passes the end of the interval,
+/// other bars are closed by the trade that completes them. Bars still open are closed in `all_done`.
+/// Bars closed at the same time are passed in ascending order of order book id.
+pub struct BarBuilder<F>
+where
+    F: FnMut(&Bar),
+{
+    pub spec: BarSpec,
+    /// close the bar when the session changes. true by default.
+    pub split_sessions: bool,
+    pub filter: ProductFilter,
+    open: HashMap<i64, OpenBar>,
+    /// earliest end of the open time bars
+    next_close: Option<NaiveDateTime>,
+    /// best bid and ask before the current message stack is processed
+    bbo_before: HashMap<i64, (Option<PriceLevelView>, Option<PriceLevelView>)>,
+    f: F,
+}
+
+impl<F> BarBuilder<F>
+where
+    F: FnMut(&Bar),
+{
+    /// panics if the size of the bar is not positive
+    pub fn new(spec: BarSpec, f: F) -> Self {
+        let valid = match spec {
+            BarSpec::Time(d) => d > Duration::zero(),
+            BarSpec::Tick(n) => n > 0,
+            BarSpec::Volume(n) => n > 0,
+            BarSpec::Notional(n) => n > 0,
+        };
+        assert!(valid, "bar size must be positive: {:?}", spec);
+        Self {
+            spec,
+            split_sessions: true,
+            filter: ProductFilter::default(),
+            open: HashMap::new(),
+            next_close: None,
+            bbo_before: HashMap::new(),
+            f,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: ProductFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn split_sessions(mut self, split_sessions: bool) -> Self {
+        self.split_sessions = split_sessions;
+        self
+    }
+
+    /// end of the time bar containing `timestamp`
+    fn interval_end(&self, timestamp: &NaiveDateTime) -> Option<NaiveDateTime> {
+        let BarSpec::Time(duration) = self.spec else {
+            return None;
+        };
+        interval_end(timestamp, duration)
+    }
+
+    fn emit(&mut self, mut open: OpenBar, bbo: (Option<PriceLevelView>, Option<PriceLevelView>)) {
+        open.bar.best_bid = bbo.0;
+        open.bar.best_ask = bbo.1;
+        (self.f)(&open.bar);
+    }
+
+    fn update_next_close(&mut self) {
+        self.next_close = self.open.values().filter_map(|o| o.end).min();
+    }
+
+    fn close_all(&mut self, order_book_map: &HashMap<i64, OrderBook>) {
+        let mut ids: Vec<i64> = self.open.keys().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            if let Some(open) = self.open.remove(&id) {
+                self.emit(open, current_bbo(order_book_map, id));
+            }
+        }
+        self.next_close = None;
+    }
+}
+
+fn current_bbo(
+    order_book_map: &HashMap<i64, OrderBook>,
+    order_book_id: i64,
+) -> (Option<PriceLevelView>, Option<PriceLevelView>) {
+    order_book_map
+        .get(&order_book_id)
+        .map(|book| (book.best_bid(), book.best_ask()))
+        .unwrap_or_default()
+}
+
+impl<F> OrderBookRunTimeCallback for BarBuilder<F>
+where
+    F: FnMut(&Bar),
+{
+    fn event_start(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        // books are not updated yet, this is the state at the end of the interval
+        if self.next_close.map(|t| t <= *timestamp).unwrap_or(false) {
+            let mut ids: Vec<i64> = self
+                .open
+                .iter()
+                .filter(|(_, o)| o.end.map(|t| t <= *timestamp).unwrap_or(false))
+                .map(|(id, _)| *id)
+                .collect();
+            ids.sort_unstable();
+            for id in ids {
+                if let Some(open) = self.open.remove(&id) {
+                    self.emit(open, current_bbo(order_book_map, id));
+                }
+            }
+            self.update_next_close();
+        }
+
+        self.bbo_before.clear();
+        for msg in stack {
+            let id = match msg {
+                MessageEnum::Executed(m) => m.order_book_id,
+                MessageEnum::ExecutionWithPriceInfo(m) => m.order_book_id,
+                MessageEnum::LegPrice(m) => m.order_book_id,
+                _ => continue,
+            };
+            if self.open.contains_key(&id) {
+                self.bbo_before
+                    .entry(id)
+                    .or_insert_with(|| current_bbo(order_book_map, id));
+            }
+        }
+    }
+
+    fn trades(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        trades: Vec<Trade>,
+    ) {
+        for trade in trades.iter() {
+            let id = trade.order_book_id;
+            if !self.filter.matches(id, order_book_map.get(&id)) {
+                continue;
+            }
+            let session = SessionId::from_timestamp(&trade.timestamp);
+            let end = self.interval_end(&trade.timestamp);
+
+            let expired = self
+                .open
+                .get(&id)
+                .map(|o| {
+                    (self.split_sessions && o.bar.session != session)
+                        || o.end.map(|e| e <= trade.timestamp).unwrap_or(false)
+                })
+                .unwrap_or(false);
+            if expired {
+                let open = self.open.remove(&id).unwrap();
+                let bbo = self.bbo_before.get(&id).copied().unwrap_or_default();
+                self.emit(open, bbo);
+            }
+
+            match self.open.get_mut(&id) {
+                Some(open) => open.bar.update(trade),
+                None => {
+                    self.open.insert(
+                        id,
+                        OpenBar {
+                            bar: Bar::new(trade, session),
+                            end,
+                        },
+                    );
+                }
+            }
+
+            if self.open[&id].bar.is_complete(&self.spec) {
+                let open = self.open.remove(&id).unwrap();
+                self.emit(open, current_bbo(order_book_map, id));
+            }
+        }
+        self.update_next_close();
+    }
+
+    fn all_done(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: Option<NaiveDateTime>,
+    ) {
+        self.close_all(order_book_map);
+    }
+}
+
+#[test]
+fn test_bar_builder() {
+    use crate::{
+        replay,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),1,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),2,FUT_NK225M_2109(590334),B,1,5,289950000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),3,FUT_NK225M_2109(590334),S,2,5,290050000,0,2",
+        "E,2021-03-01T00:00:10.000000000(1614556810000000000),1,FUT_NK225M_2109(590334),S,2,100,0,,",
+        "E,2021-03-01T00:00:20.000000000(1614556820000000000),2,FUT_NK225M_2109(590334),B,1,101,0,,",
+        "E,2021-03-01T00:00:30.000000000(1614556830000000000),1,FUT_NK225M_2109(590334),S,3,102,0,,",
+        "E,2021-03-01T00:01:10.000000000(1614556870000000000),3,FUT_NK225M_2109(590334),S,1,103,0,,",
+    ];
+
+    let run = |spec| {
+        let mut bars = vec![];
+        replay(
+            &file,
+            &mut BarBuilder::new(spec, |bar: &Bar| bars.push(bar.clone())),
+        );
+        bars
+    };
+
+    let bars = run(BarSpec::Time(Duration::minutes(1)));
+    assert_eq!(bars.len(), 2);
+    let bar = &bars[0];
+    assert_eq!(
+        (bar.open, bar.high, bar.low, bar.close),
+        (290000000, 290000000, 289950000, 290000000)
+    );
+    assert_eq!(bar.volume, 6);
+    assert_eq!(bar.trade_count, 3);
+    assert_eq!((bar.buy_volume, bar.sell_volume), (5, 1));
+    assert_eq!(bar.vwap, (290000000.0 * 5.0 + 289950000.0) / 6.0);
+    // closed at the start of the next interval, before the trade at 00:01:10
+    assert_eq!(bar.best_ask.map(|l| l.price), Some(290050000));
+    assert_eq!(bar.best_bid.map(|l| l.qty), Some(4));
+    assert_eq!(bars[1].open, 290050000);
+
+    let bars = run(BarSpec::Volume(3));
+    assert_eq!(
+        bars.iter().map(|b| b.volume).collect::<Vec<_>>(),
+        vec![3, 3, 1]
+    );
+    assert_eq!(bars[1].close, 290000000);
+
+    let bars = run(BarSpec::Tick(3));
+    assert_eq!(
+        bars.iter().map(|b| b.trade_count).collect::<Vec<_>>(),
+        vec![3, 1]
+    );
+
+    let mut sink = BarCsvWriter::new(vec![]);
+    for bar in bars.iter() {
+        sink.write(bar);
+    }
+    assert!(sink.flush().is_ok());
+    let csv = String::from_utf8(sink.into_inner()).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], Bar::CSV_HEADER);
+    assert_eq!(lines[1], bars[0].to_csv());
+}
@@ -0,0 +1,466 @@
+use std::collections::{
+    BTreeSet,
+    HashMap,
+    HashSet,
+};
+use std::f64::consts::{
+    PI,
+    SQRT_2,
+};
+
+use chrono::{
+    NaiveDate,
+    NaiveDateTime,
+    NaiveTime,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    FinancialProduct,
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    ProductInfo,
+    PutOrCall,
+};
+
+/// Day count convention used to compute time to expiry in years
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DayCount {
+    #[default]
+    Actual365Fixed,
+    Actual360,
+}
+
+impl DayCount {
+    /// year fraction between `from` and `to`. Negative if `to` is before `from`.
+    pub fn year_fraction(&self, from: NaiveDateTime, to: NaiveDateTime) -> f64 {
+        let days = (to - from).num_nanoseconds().unwrap_or(i64::MAX) as f64 / 86_400e9;
+        match self {
+            DayCount::Actual365Fixed => days / 365.0,
+            DayCount::Actual360 => days / 360.0,
+        }
+    }
+}
+
+/// Parameters for Black-76 analytics
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Black76Config {
+    /// continuously compounded interest rate used for discounting
+    pub rate: f64,
+    pub day_count: DayCount,
+    /// time of the expiration date at which options expire, in the same timezone as the timestamps (UTC).
+    /// Default is 00:00 UTC (09:00 JST), SQ is calculated with the opening prices.
+    pub expiry_time: NaiveTime,
+    /// order book id of the future used as forward, keyed by `underlying_order_book_id` of options.
+    ///
+    /// `underlying_order_book_id` is used as is when it's not in this map.
+    /// On OSE data it often points at the index rather than a future, so this needs to be set in most cases.
+    pub forward_order_book_ids: HashMap<i64, i64>,
+}
+
+impl Default for Black76Config {
+    fn default() -> Self {
+        Self {
+            rate: 0.0,
+            day_count: DayCount::default(),
+            expiry_time: NaiveTime::MIN,
+            forward_order_book_ids: HashMap::new(),
+        }
+    }
+}
+
+/// Black-76 sensitivities.
+///
+/// `vega` is per 1.00 change of volatility and `theta` is per year. Divide by 100 and 365 for per vol point and per day.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct Greeks {
+    pub delta: f64,
+    pub gamma: f64,
+    pub vega: f64,
+    pub theta: f64,
+}
+
+/// Implied volatility and greeks of an option book at a timestamp.
+///
+/// Prices are converted to floating point using `number_of_decimal_in_price` and `number_of_decimals_in_strike_price`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct OptionAnalytics {
+    pub timestamp: NaiveDateTime,
+    pub order_book_id: i64,
+    /// order book id of the future used as forward
+    pub forward_order_book_id: i64,
+    pub put_or_call: PutOrCall,
+    pub expiration_date: i64,
+    pub strike: f64,
+    /// mid price of the forward
+    pub forward: f64,
+    pub time_to_expiry: f64,
+    pub bid: Option<f64>,
+    pub ask: Option<f64>,
+    pub bid_iv: Option<f64>,
+    pub ask_iv: Option<f64>,
+    /// implied volatility of mid price. None if bid or ask is missing.
+    pub mid_iv: Option<f64>,
+    /// greeks at `mid_iv`
+    pub greeks: Option<Greeks>,
+}
+
+/// standard normal density
+fn norm_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * PI).sqrt()
+}
+
+/// complementary error function.
+/// Power series for small arguments and continued fraction for the tail.
+fn erfc(x: f64) -> f64 {
+    if x < 0.0 {
+        return 2.0 - erfc(-x);
+    }
+    if x < 3.0 {
+        // erf(x) = 2/sqrt(pi) * sum (-1)^n x^(2n+1) / (n! (2n+1))
+        let mut term = x;
+        let mut sum = x;
+        let mut n = 0.0;
+        while term.abs() > 1e-17 * sum.abs() {
+            n += 1.0;
+            term *= -x * x / n;
+            sum += term / (2.0 * n + 1.0);
+        }
+        return 1.0 - 2.0 / PI.sqrt() * sum;
+    }
+    let mut t = x;
+    for k in (1..=60).rev() {
+        t = x + (k as f64 / 2.0) / t;
+    }
+    (-x * x).exp() / PI.sqrt() / t
+}
+
+/// standard normal cumulative distribution
+fn norm_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / SQRT_2)
+}
+
+fn d1_d2(forward: f64, strike: f64, time_to_expiry: f64, vol: f64) -> (f64, f64) {
+    let sd = vol * time_to_expiry.sqrt();
+    let d1 = ((forward / strike).ln() + 0.5 * sd * sd) / sd;
+    (d1, d1 - sd)
+}
+
+/// Black-76 price of an european option on a future
+pub fn black76_price(
+    put_or_call: PutOrCall,
+    forward: f64,
+    strike: f64,
+    time_to_expiry: f64,
+    vol: f64,
+    rate: f64,
+) -> f64 {
+    let df = (-rate * time_to_expiry).exp();
+    let (d1, d2) = d1_d2(forward, strike, time_to_expiry, vol);
+    match put_or_call {
+        PutOrCall::Put => df * (strike * norm_cdf(-d2) - forward * norm_cdf(-d1)),
+        _ => df * (forward * norm_cdf(d1) - strike * norm_cdf(d2)),
+    }
+}
+
+/// Black-76 greeks. See `Greeks` for the units.
+pub fn black76_greeks(
+    put_or_call: PutOrCall,
+    forward: f64,
+    strike: f64,
+    time_to_expiry: f64,
+    vol: f64,
+    rate: f64,
+) -> Greeks {
+    let df = (-rate * time_to_expiry).exp();
+    let sqrt_t = time_to_expiry.sqrt();
+    let (d1, _) = d1_d2(forward, strike, time_to_expiry, vol);
+    let price = black76_price(put_or_call, forward, strike, time_to_expiry, vol, rate);
+    let delta = match put_or_call {
+        PutOrCall::Put => -df * norm_cdf(-d1),
+        _ => df * norm_cdf(d1),
+    };
+    Greeks {
+        delta,
+        gamma: df * norm_pdf(d1) / (forward * vol * sqrt_t),
+        vega: df * forward * norm_pdf(d1) * sqrt_t,
+        theta: rate * price - df * forward * norm_pdf(d1) * vol / (2.0 * sqrt_t),
+    }
+}
+
+/// Black-76 implied volatility.
+///
+/// returns None if the price is outside of the no-arbitrage bounds, the inputs are not positive
+/// or no volatility between 1e-6 and 10 reproduces the price.
+pub fn implied_volatility(
+    put_or_call: PutOrCall,
+    price: f64,
+    forward: f64,
+    strike: f64,
+    time_to_expiry: f64,
+    rate: f64,
+) -> Option<f64> {
+    if !(price > 0.0 && forward > 0.0 && strike > 0.0 && time_to_expiry > 0.0) {
+        return None;
+    }
+    let df = (-rate * time_to_expiry).exp();
+    let (lower, upper) = match put_or_call {
+        PutOrCall::Put => (df * (strike - forward).max(0.0), df * strike),
+        _ => (df * (forward - strike).max(0.0), df * forward),
+    };
+    if price <= lower || price >= upper {
+        return None;
+    }
+
+    // newton's method, falls back to bisection when it leaves the bracket
+    let (mut lo, mut hi) = (1e-6, 10.0);
+    let mut vol = 0.2;
+    for _ in 0..200 {
+        let diff = black76_price(put_or_call, forward, strike, time_to_expiry, vol, rate) - price;
+        if diff.abs() < 1e-12 * upper.max(1.0) {
+            return Some(vol);
+        }
+        if diff > 0.0 {
+            hi = vol;
+        } else {
+            lo = vol;
+        }
+        let vega = black76_greeks(put_or_call, forward, strike, time_to_expiry, vol, rate).vega;
+        let next = vol - diff / vega;
+        vol = if vega > 0.0 && next > lo && next < hi {
+            next
+        } else {
+            0.5 * (lo + hi)
+        };
+        if hi - lo < 1e-12 {
+            break;
+        }
+    }
+    None
+}
+
+fn scale(value: i64, decimals: i64) -> f64 {
+    value as f64 / 10f64.powi(decimals as i32)
+}
+
+impl Black76Config {
+    /// order book id of the future used as forward for the option
+    pub fn forward_order_book_id(&self, info: &ProductInfo) -> i64 {
+        self.forward_order_book_ids
+            .get(&info.underlying_order_book_id)
+            .copied()
+            .unwrap_or(info.underlying_order_book_id)
+    }
+
+    /// time to expiry in years. None if `expiration_date` is not a valid `yyyymmdd`.
+    pub fn time_to_expiry(&self, timestamp: &NaiveDateTime, expiration_date: i64) -> Option<f64> {
+        let date = NaiveDate::from_ymd_opt(
+            (expiration_date / 10000) as i32,
+            (expiration_date / 100 % 100) as u32,
+            (expiration_date % 100) as u32,
+        )?;
+        Some(
+            self.day_count
+                .year_fraction(*timestamp, date.and_time(self.expiry_time)),
+        )
+    }
+
+    /// computes analytics of the option with the current state of the books.
+    ///
+    /// returns None if the order book is not an option, the forward has no bid or ask, or the option has expired.
+    pub fn analyze(
+        &self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        order_book_id: i64,
+        timestamp: &NaiveDateTime,
+    ) -> Option<OptionAnalytics> {
+        let book = order_book_map.get(&order_book_id)?;
+        let info = &book.product_info;
+        if info.financial_product != FinancialProduct::Option || info.put_or_call.is_combo() {
+            return None;
+        }
+
+        let forward_order_book_id = self.forward_order_book_id(info);
+        let forward_book = order_book_map.get(&forward_order_book_id)?;
+        let forward_decimals = forward_book.product_info.number_of_decimal_in_price;
+        let forward = {
+            let bid = forward_book.best_bid()?;
+            let ask = forward_book.best_ask()?;
+            scale(bid.price + ask.price, forward_decimals) / 2.0
+        };
+
+        let time_to_expiry = self.time_to_expiry(timestamp, info.expiration_date)?;
+        if time_to_expiry <= 0.0 {
+            return None;
+        }
+
+        let strike = scale(info.strike_price, info.number_of_decimals_in_strike_price);
+        let decimals = info.number_of_decimal_in_price;
+        let bid = book.best_bid().map(|v| scale(v.price, decimals));
+        let ask = book.best_ask().map(|v| scale(v.price, decimals));
+        let iv = |price: f64| {
+            implied_volatility(
+                info.put_or_call,
+                price,
+                forward,
+                strike,
+                time_to_expiry,
+                self.rate,
+            )
+        };
+        let mid_iv = bid.zip(ask).and_then(|(b, a)| iv((a + b) / 2.0));
+
+        Some(OptionAnalytics {
+            timestamp: *timestamp,
+            order_book_id,
+            forward_order_book_id,
+            put_or_call: info.put_or_call,
+            expiration_date: info.expiration_date,
+            strike,
+            forward,
+            time_to_expiry,
+            bid,
+            ask,
+            bid_iv: bid.and_then(iv),
+            ask_iv: ask.and_then(iv),
+            mid_iv,
+            greeks: mid_iv.map(|vol| {
+                black76_greeks(
+                    info.put_or_call,
+                    forward,
+                    strike,
+                    time_to_expiry,
+                    vol,
+                    self.rate,
+                )
+            }),
+        })
+    }
+}
+
+/// Calls `callback` with `OptionAnalytics` of every option whose book, or the book of its forward, changed.
+///
+/// ```ignore
+/// let mut series = vec![];
+/// let mut cb = Black76Callback::new(config, |a: &OptionAnalytics| series.push(*a));
+/// order_book_runtime(&mut map, itch.into_iter(), &mut cb);
+/// ```
+pub struct Black76Callback<F>
+where
+    F: FnMut(&OptionAnalytics),
+{
+    pub config: Black76Config,
+    callback: F,
+    /// forward order book id => options
+    options_by_forward: HashMap<i64, BTreeSet<i64>>,
+}
+
+impl<F> Black76Callback<F>
+where
+    F: FnMut(&OptionAnalytics),
+{
+    pub fn new(config: Black76Config, callback: F) -> Self {
+        Self {
+            config,
+            callback,
+            options_by_forward: HashMap::new(),
+        }
+    }
+
+    fn register(&mut self, info: &ProductInfo) {
+        if info.financial_product == FinancialProduct::Option && !info.put_or_call.is_combo() {
+            self.options_by_forward
+                .entry(self.config.forward_order_book_id(info))
+                .or_default()
+                .insert(info.order_book_id);
+        }
+    }
+}
+
+impl<F> OrderBookRunTimeCallback for Black76Callback<F>
+where
+    F: FnMut(&OptionAnalytics),
+{
+    fn event_start(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        for msg in stack {
+            if let MessageEnum::ProductInfo(info) = msg {
+                self.register(info);
+            }
+        }
+    }
+
+    fn order_book_id_with_changes(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        changes: &HashSet<i64>,
+    ) {
+        let mut targets = BTreeSet::new();
+        for id in changes {
+            if let Some(options) = self.options_by_forward.get(id) {
+                targets.extend(options.iter().copied());
+            }
+            targets.insert(*id);
+        }
+        for id in targets {
+            if let Some(analytics) = self.config.analyze(order_book_map, id, timestamp) {
+                (self.callback)(&analytics);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_black76() {
+    let (f, k, t, r) = (29000.0, 28000.0, 0.25, 0.01);
+    let call = black76_price(PutOrCall::Call, f, k, t, 0.2, r);
+    let put = black76_price(PutOrCall::Put, f, k, t, 0.2, r);
+    // put call parity
+    assert!((call - put - (-r * t).exp() * (f - k)).abs() < 1e-8);
+
+    for vol in [0.05, 0.2, 0.8] {
+        for poc in [PutOrCall::Call, PutOrCall::Put] {
+            let price = black76_price(poc, f, k, t, vol, r);
+            let iv = implied_volatility(poc, price, f, k, t, r).unwrap();
+            assert!((iv - vol).abs() < 1e-8, "{poc:?} {vol} {iv}");
+        }
+    }
+    // within the bounds, but above the price at the highest volatility searched
+    assert!(black76_price(PutOrCall::Call, f, k, t, 10.0, r) < 28900.0);
+    assert_eq!(
+        implied_volatility(PutOrCall::Call, 28900.0, f, k, t, r),
+        None
+    );
+
+    // finite differences
+    let g = black76_greeks(PutOrCall::Call, f, k, t, 0.2, r);
+    let h = 1e-3;
+    let delta = (black76_price(PutOrCall::Call, f + h, k, t, 0.2, r)
+        - black76_price(PutOrCall::Call, f - h, k, t, 0.2, r))
+        / (2.0 * h);
+    let vega = (black76_price(PutOrCall::Call, f, k, t, 0.2 + 1e-6, r)
+        - black76_price(PutOrCall::Call, f, k, t, 0.2 - 1e-6, r))
+        / 2e-6;
+    let theta = -(black76_price(PutOrCall::Call, f, k, t + 1e-6, 0.2, r)
+        - black76_price(PutOrCall::Call, f, k, t - 1e-6, 0.2, r))
+        / 2e-6;
+    assert!((g.delta - delta).abs() < 1e-6);
+    assert!((g.vega - vega).abs() < 1e-4);
+    assert!((g.theta - theta).abs() < 1e-3);
+
+    // below intrinsic value
+    assert_eq!(
+        implied_volatility(PutOrCall::Call, 900.0, f, k, t, 0.0),
+        None
+    );
+    assert!((norm_cdf(-6.0) - 9.865876450377e-10).abs() < 1e-20);
+}
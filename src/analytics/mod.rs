@@ -0,0 +1,57 @@
+#[cfg(feature = "parquet")]
+mod bar_parquet;
+#[cfg(feature = "parquet")]
+pub use bar_parquet::BarParquetWriter;
+
+mod bars;
+pub use bars::{
+    Bar,
+    BarBuilder,
+    BarCsvWriter,
+    BarSpec,
+};
+
+mod black76;
+pub use black76::{
+    black76_greeks,
+    black76_price,
+    implied_volatility,
+    Black76Callback,
+    Black76Config,
+    DayCount,
+    Greeks,
+    OptionAnalytics,
+};
+
+mod order_flow;
+pub use order_flow::{
+    order_flow_imbalance,
+    OrderFlow,
+    OrderFlowRecord,
+    OrderFlowTracker,
+};
+
+mod order_statistics;
+pub use order_statistics::{
+    OrderStatistics,
+    OrderStatisticsAggregator,
+};
+
+mod surveillance;
+pub use surveillance::{
+    AddCancelBurstDetector,
+    Alert,
+    AwayFromTouchCancelDetector,
+    Detector,
+    SurveillanceRunner,
+};
+
+mod vol_surface;
+pub use vol_surface::{
+    SviParams,
+    VolPoint,
+    VolSlice,
+    VolSurface,
+    VolSurfaceConfig,
+    VolSurfaceRecorder,
+};
@@ -0,0 +1,434 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+    VecDeque,
+};
+
+use chrono::{
+    Duration,
+    NaiveDateTime,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::callback_datatype::{
+    AggressorSide,
+    Created,
+    ModifiedOrder,
+    OrderDeletion,
+    Trade,
+};
+use crate::{
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    PriceLevelView,
+    ProductFilter,
+    Side,
+};
+
+/// order flow of an order book in a single message stack, or summed over a window
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OrderFlow {
+    /// order flow imbalance at the best prices. See `order_flow_imbalance`
+    pub ofi: i64,
+    /// buy initiated volume - sell initiated volume
+    pub signed_volume: i64,
+    pub buy_volume: i64,
+    pub sell_volume: i64,
+    /// `A` tags near the touch, modifications excluded
+    pub adds_near_touch: usize,
+    /// `D` tags near the touch, modifications excluded
+    pub cancels_near_touch: usize,
+    /// modified orders whose previous price was near the touch
+    pub modifies_near_touch: usize,
+}
+
+impl OrderFlow {
+    fn add(&mut self, other: &OrderFlow) {
+        self.ofi += other.ofi;
+        self.signed_volume += other.signed_volume;
+        self.buy_volume += other.buy_volume;
+        self.sell_volume += other.sell_volume;
+        self.adds_near_touch += other.adds_near_touch;
+        self.cancels_near_touch += other.cancels_near_touch;
+        self.modifies_near_touch += other.modifies_near_touch;
+    }
+
+    fn sub(&mut self, other: &OrderFlow) {
+        self.ofi -= other.ofi;
+        self.signed_volume -= other.signed_volume;
+        self.buy_volume -= other.buy_volume;
+        self.sell_volume -= other.sell_volume;
+        self.adds_near_touch -= other.adds_near_touch;
+        self.cancels_near_touch -= other.cancels_near_touch;
+        self.modifies_near_touch -= other.modifies_near_touch;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OrderFlowRecord {
+    pub timestamp: NaiveDateTime,
+    pub order_book_id: i64,
+    pub best_bid: Option<PriceLevelView>,
+    pub best_ask: Option<PriceLevelView>,
+    /// this message stack only
+    pub flow: OrderFlow,
+    /// sums over `OrderFlowTracker::windows`, in the same order.
+    /// A window of `d` covers message stacks in `(timestamp - d, timestamp]`.
+    pub rolling: Vec<OrderFlow>,
+}
+
+/// Order flow imbalance of Cont, Kukanov and Stoikov between two states of the best prices.
+///
+/// A missing side counts as zero quantity at a price worse than any other.
+pub fn order_flow_imbalance(
+    previous: (Option<PriceLevelView>, Option<PriceLevelView>),
+    current: (Option<PriceLevelView>, Option<PriceLevelView>),
+) -> i64 {
+    let qty = |l: Option<PriceLevelView>| l.map(|l| l.qty).unwrap_or(0);
+    let bid_price = |l: Option<PriceLevelView>| l.map(|l| l.price).unwrap_or(i64::MIN);
+    let ask_price = |l: Option<PriceLevelView>| l.map(|l| l.price).unwrap_or(i64::MAX);
+
+    let (prev_bid, prev_ask) = previous;
+    let (bid, ask) = current;
+    let mut e = 0;
+    if bid_price(bid) >= bid_price(prev_bid) {
+        e += qty(bid);
+    }
+    if bid_price(bid) <= bid_price(prev_bid) {
+        e -= qty(prev_bid);
+    }
+    if ask_price(ask) <= ask_price(prev_ask) {
+        e -= qty(ask);
+    }
+    if ask_price(ask) >= ask_price(prev_ask) {
+        e += qty(prev_ask);
+    }
+    e
+}
+
+#[derive(Default)]
+struct BookFlowState {
+    /// (timestamp, flow) of the message stacks within the longest window
+    history: VecDeque<(NaiveDateTime, OrderFlow)>,
+    /// (number of the last entries of `history` in the window, sum of them) for each window
+    rolling: Vec<(usize, OrderFlow)>,
+}
+
+/// Computes `OrderFlowRecord` for every order book touched by a message stack, and passes them to `f`
+/// in ascending order of order book id at the end of the stack.
+pub struct OrderFlowTracker<F>
+where
+    F: FnMut(&OrderFlowRecord),
+{
+    pub windows: Vec<Duration>,
+    /// orders within this many ticks from the best price of the same side (before the stack) are near the touch.
+    /// Orders on an empty side are always near the touch.
+    pub near_touch_ticks: f64,
+    pub filter: ProductFilter,
+    /// best bid and ask before the current message stack is processed
+    bbo_before: HashMap<i64, (Option<PriceLevelView>, Option<PriceLevelView>)>,
+    current: HashMap<i64, OrderFlow>,
+    books: HashMap<i64, BookFlowState>,
+    f: F,
+}
+
+impl<F> OrderFlowTracker<F>
+where
+    F: FnMut(&OrderFlowRecord),
+{
+    pub fn new(windows: Vec<Duration>, f: F) -> Self {
+        Self {
+            windows,
+            near_touch_ticks: 1.0,
+            filter: ProductFilter::default(),
+            bbo_before: HashMap::new(),
+            current: HashMap::new(),
+            books: HashMap::new(),
+            f,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: ProductFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    fn is_near_touch(&self, book: &OrderBook, side: Side, price: i64) -> bool {
+        let (bid, ask) = self
+            .bbo_before
+            .get(&book.order_book_id())
+            .copied()
+            .unwrap_or_default();
+        let best = match side {
+            Side::Buy => bid,
+            Side::Sell => ask,
+        };
+        let Some(best) = best else {
+            return true;
+        };
+        let at_or_better = match side {
+            Side::Buy => price >= best.price,
+            Side::Sell => price <= best.price,
+        };
+        at_or_better
+            || book
+                .ticks_between(price as f64, best.price as f64)
+                .map(|ticks| ticks <= self.near_touch_ticks)
+                .unwrap_or(false)
+    }
+
+    fn flow_mut(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        order_book_id: i64,
+    ) -> Option<&mut OrderFlow> {
+        if !self
+            .filter
+            .matches(order_book_id, order_book_map.get(&order_book_id))
+        {
+            return None;
+        }
+        Some(self.current.entry(order_book_id).or_default())
+    }
+}
+
+impl<F> OrderBookRunTimeCallback for OrderFlowTracker<F>
+where
+    F: FnMut(&OrderFlowRecord),
+{
+    fn event_start(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        self.bbo_before.clear();
+        self.current.clear();
+        for msg in stack {
+            let id = match msg {
+                MessageEnum::AddOrder(m) => m.order_book_id,
+                MessageEnum::DeleteOrder(m) => m.order_book_id,
+                MessageEnum::Executed(m) => m.order_book_id,
+                MessageEnum::ExecutionWithPriceInfo(m) => m.order_book_id,
+                _ => continue,
+            };
+            if let Some(book) = order_book_map.get(&id) {
+                self.bbo_before
+                    .entry(id)
+                    .or_insert_with(|| (book.best_bid(), book.best_ask()));
+            }
+        }
+    }
+
+    fn created(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        created: Created,
+    ) {
+        for add in created.msgs.iter() {
+            let Some(book) = order_book_map.get(&add.order_book_id) else {
+                continue;
+            };
+            if self.is_near_touch(book, add.side, add.price) {
+                if let Some(flow) = self.flow_mut(order_book_map, add.order_book_id) {
+                    flow.adds_near_touch += 1;
+                }
+            }
+        }
+    }
+
+    fn trades(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        trades: Vec<Trade>,
+    ) {
+        for trade in trades.iter() {
+            let Some(flow) = self.flow_mut(order_book_map, trade.order_book_id) else {
+                continue;
+            };
+            match trade.aggressor_side {
+                AggressorSide::Buy => flow.buy_volume += trade.quantity,
+                AggressorSide::Sell => flow.sell_volume += trade.quantity,
+                AggressorSide::Auction | AggressorSide::Unknown => (),
+            }
+            flow.signed_volume = flow.buy_volume - flow.sell_volume;
+        }
+    }
+
+    fn deletions(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        deletion: Vec<OrderDeletion>,
+    ) {
+        for d in deletion.iter() {
+            let order = &d.deleted_order;
+            let Some(book) = order_book_map.get(&order.order_book_id) else {
+                continue;
+            };
+            if self.is_near_touch(book, order.side, order.price) {
+                if let Some(flow) = self.flow_mut(order_book_map, order.order_book_id) {
+                    flow.cancels_near_touch += 1;
+                }
+            }
+        }
+    }
+
+    fn modified_orders(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        modified_orders: Vec<ModifiedOrder>,
+    ) {
+        for m in modified_orders.iter() {
+            let order = &m.previous_add_order;
+            let Some(book) = order_book_map.get(&order.order_book_id) else {
+                continue;
+            };
+            if self.is_near_touch(book, order.side, order.price) {
+                if let Some(flow) = self.flow_mut(order_book_map, order.order_book_id) {
+                    flow.modifies_near_touch += 1;
+                }
+            }
+        }
+    }
+
+    fn order_book_id_with_changes(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        changes: &HashSet<i64>,
+    ) {
+        for id in changes {
+            let Some(book) = order_book_map.get(id) else {
+                continue;
+            };
+            let previous = self.bbo_before.get(id).copied().unwrap_or_default();
+            let ofi = order_flow_imbalance(previous, (book.best_bid(), book.best_ask()));
+            if let Some(flow) = self.flow_mut(order_book_map, *id) {
+                flow.ofi = ofi;
+            }
+        }
+    }
+
+    fn event_end(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        _stack: &[MessageEnum],
+    ) {
+        let mut ids: Vec<i64> = self.current.keys().copied().collect();
+        ids.sort_unstable();
+
+        for id in ids {
+            let flow = self.current[&id];
+            let state = self.books.entry(id).or_default();
+            state
+                .rolling
+                .resize(self.windows.len(), (0, OrderFlow::default()));
+            state.history.push_back((*timestamp, flow));
+
+            for (window, (len, sum)) in self.windows.iter().zip(state.rolling.iter_mut()) {
+                *len += 1;
+                sum.add(&flow);
+                // drop the oldest entries of the window
+                while *len > 0 {
+                    let (t, old) = &state.history[state.history.len() - *len];
+                    if *t > *timestamp - *window {
+                        break;
+                    }
+                    sum.sub(old);
+                    *len -= 1;
+                }
+            }
+            let kept = state.rolling.iter().map(|(len, _)| *len).max().unwrap_or(0);
+            while state.history.len() > kept {
+                state.history.pop_front();
+            }
+
+            let (best_bid, best_ask) = order_book_map
+                .get(&id)
+                .map(|book| (book.best_bid(), book.best_ask()))
+                .unwrap_or_default();
+            (self.f)(&OrderFlowRecord {
+                timestamp: *timestamp,
+                order_book_id: id,
+                best_bid,
+                best_ask,
+                flow,
+                rolling: state.rolling.iter().map(|(_, sum)| *sum).collect(),
+            });
+        }
+    }
+}
+
+#[test]
+fn test_order_flow_imbalance() {
+    let l = |price, qty| Some(PriceLevelView { price, qty });
+    // bid qty increased at the same price
+    assert_eq!(
+        order_flow_imbalance((l(100, 5), l(101, 5)), (l(100, 8), l(101, 5))),
+        3
+    );
+    // bid price moved up
+    assert_eq!(
+        order_flow_imbalance((l(100, 5), l(102, 5)), (l(101, 2), l(102, 5))),
+        2
+    );
+    // ask price moved down
+    assert_eq!(
+        order_flow_imbalance((l(100, 5), l(102, 5)), (l(100, 5), l(101, 4))),
+        -4
+    );
+    // ask side emptied
+    assert_eq!(
+        order_flow_imbalance((l(100, 5), l(102, 5)), (l(100, 5), None)),
+        5
+    );
+}
+
+#[test]
+fn test_order_flow_tracker() {
+    use crate::{
+        replay,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "L,2021-02-28T21:07:50.931282000(1614546470931282000),FUT_NK225M_2109(590334),50000,0,999999999",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        // 2 ticks away from the best bid
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),3,FUT_NK225M_2109(590334),B,1,5,289800000,0,2",
+        "E,2021-03-01T00:00:03.000000000(1614556803000000000),2,FUT_NK225M_2109(590334),S,2,100,0,,",
+        "D,2021-03-01T00:00:10.000000000(1614556810000000000),1,FUT_NK225M_2109(590334),B",
+    ];
+
+    let mut records = vec![];
+    let mut tracker = OrderFlowTracker::new(vec![Duration::seconds(5)], |r: &OrderFlowRecord| {
+        records.push(r.clone())
+    });
+    replay(&file, &mut tracker);
+
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[0].flow.ofi, 0);
+    assert_eq!(records[0].flow.adds_near_touch, 2);
+    assert_eq!(records[1].flow.adds_near_touch, 0);
+    // ask qty reduced by 2
+    assert_eq!(records[2].flow.ofi, 2);
+    assert_eq!(records[2].flow.signed_volume, 2);
+    assert_eq!(records[2].rolling[0].adds_near_touch, 2);
+    assert_eq!(records[2].rolling[0].signed_volume, 2);
+    // best bid deleted, next bid 289800000 x 5
+    assert_eq!(records[3].flow.ofi, -5);
+    assert_eq!(records[3].flow.cancels_near_touch, 1);
+    // earlier stacks are out of the window
+    assert_eq!(records[3].rolling[0], records[3].flow);
+}
@@ -0,0 +1,285 @@
+use std::collections::{
+    BTreeMap,
+    HashMap,
+};
+
+use chrono::{
+    Duration,
+    NaiveDateTime,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::callback_datatype::{
+    CTagWithCorrespondingPTag,
+    Created,
+    ModifiedOrder,
+    ModifyType,
+    OrderDeletion,
+    OrderExecution,
+    Trade,
+    TradeSource,
+};
+use crate::{
+    AddOrder,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    SessionId,
+    UniqueId,
+};
+
+/// order activity of an order book in a session
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OrderStatistics {
+    pub order_book_id: i64,
+    pub session: SessionId,
+    /// new orders, modifications excluded
+    pub adds: usize,
+    pub cancels: usize,
+    /// modifications reducing quantity. `ModifyType::Both` is counted here and in `price_changes`.
+    pub reduce_qty: usize,
+    pub price_changes: usize,
+    /// trades executing orders of the book. See `Trade`.
+    /// Leg prices of combination trades (`TradeSource::LegPrice`) are not fills of the leg book.
+    pub fills: usize,
+    pub filled_qty: i64,
+    /// cancels / fills. None without fills.
+    pub cancel_to_trade_ratio: Option<f64>,
+    /// median lifetime of orders which were cancelled or fully filled in the session.
+    /// Orders created before the aggregator was attached are not counted.
+    pub median_lifetime: Option<Duration>,
+    /// orders cancelled within `OrderStatisticsAggregator::fast_cancel_threshold` / adds. None without adds.
+    pub fast_cancel_share: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Accumulator {
+    adds: usize,
+    cancels: usize,
+    reduce_qty: usize,
+    price_changes: usize,
+    fills: usize,
+    filled_qty: i64,
+    fast_cancels: usize,
+    lifetimes: Vec<Duration>,
+}
+
+/// Aggregates order activity per order book and session. Read the result with `statistics`.
+#[derive(Debug, Clone)]
+pub struct OrderStatisticsAggregator {
+    /// cancels within this time after creation count as fast cancels. 100ms by default.
+    pub fast_cancel_threshold: Duration,
+    stats: BTreeMap<(i64, SessionId), Accumulator>,
+    /// creation time of orders on the book
+    created_at: HashMap<UniqueId, NaiveDateTime>,
+}
+
+impl Default for OrderStatisticsAggregator {
+    fn default() -> Self {
+        Self::new(Duration::milliseconds(100))
+    }
+}
+
+impl OrderStatisticsAggregator {
+    pub fn new(fast_cancel_threshold: Duration) -> Self {
+        Self {
+            fast_cancel_threshold,
+            stats: BTreeMap::new(),
+            created_at: HashMap::new(),
+        }
+    }
+
+    fn entry(&mut self, order_book_id: i64, timestamp: &NaiveDateTime) -> &mut Accumulator {
+        self.stats
+            .entry((order_book_id, SessionId::from_timestamp(timestamp)))
+            .or_default()
+    }
+
+    /// removes the order and records its lifetime
+    fn order_ended(&mut self, id: &UniqueId, timestamp: &NaiveDateTime) -> Option<Duration> {
+        let lifetime = *timestamp - self.created_at.remove(id)?;
+        self.entry(id.order_book_id, timestamp)
+            .lifetimes
+            .push(lifetime);
+        Some(lifetime)
+    }
+
+    fn order_filled(&mut self, add: &AddOrder, timestamp: &NaiveDateTime) {
+        if add.quantity == 0 {
+            self.order_ended(&UniqueId::from_add_order(add), timestamp);
+        }
+    }
+
+    /// statistics in ascending order of order book id and session
+    pub fn statistics(&self) -> Vec<OrderStatistics> {
+        self.stats
+            .iter()
+            .map(|((order_book_id, session), acc)| {
+                let mut lifetimes = acc.lifetimes.clone();
+                lifetimes.sort_unstable();
+                let median_lifetime = match lifetimes.len() {
+                    0 => None,
+                    n if n % 2 == 1 => Some(lifetimes[n / 2]),
+                    n => Some((lifetimes[n / 2 - 1] + lifetimes[n / 2]) / 2),
+                };
+                OrderStatistics {
+                    order_book_id: *order_book_id,
+                    session: *session,
+                    adds: acc.adds,
+                    cancels: acc.cancels,
+                    reduce_qty: acc.reduce_qty,
+                    price_changes: acc.price_changes,
+                    fills: acc.fills,
+                    filled_qty: acc.filled_qty,
+                    cancel_to_trade_ratio: (acc.fills > 0)
+                        .then(|| acc.cancels as f64 / acc.fills as f64),
+                    median_lifetime,
+                    fast_cancel_share: (acc.adds > 0)
+                        .then(|| acc.fast_cancels as f64 / acc.adds as f64),
+                }
+            })
+            .collect()
+    }
+}
+
+impl OrderBookRunTimeCallback for OrderStatisticsAggregator {
+    fn created(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        created: Created,
+    ) {
+        for add in created.msgs.iter() {
+            self.created_at
+                .insert(UniqueId::from_add_order(add), add.timestamp);
+            self.entry(add.order_book_id, timestamp).adds += 1;
+        }
+    }
+
+    fn executions(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        executions: Vec<OrderExecution>,
+    ) {
+        for execution in executions.iter() {
+            self.order_filled(&execution.matched_order_after_execution, timestamp);
+        }
+    }
+
+    fn ctag_execution(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        executed_with_price_info: Vec<CTagWithCorrespondingPTag>,
+    ) {
+        for group in executed_with_price_info.iter() {
+            for add in group.matched_add_order.iter() {
+                self.order_filled(add, timestamp);
+            }
+        }
+    }
+
+    fn trades(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        trades: Vec<Trade>,
+    ) {
+        for trade in trades.iter() {
+            if trade.source == TradeSource::LegPrice {
+                continue;
+            }
+            let acc = self.entry(trade.order_book_id, timestamp);
+            acc.fills += 1;
+            acc.filled_qty += trade.quantity;
+        }
+    }
+
+    fn deletions(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        deletion: Vec<OrderDeletion>,
+    ) {
+        for d in deletion.iter() {
+            let lifetime = self.order_ended(&UniqueId::from_delete_order(&d.msg), timestamp);
+            let fast = lifetime
+                .map(|l| l <= self.fast_cancel_threshold)
+                .unwrap_or(false);
+            let acc = self.entry(d.msg.order_book_id, timestamp);
+            acc.cancels += 1;
+            if fast {
+                acc.fast_cancels += 1;
+            }
+        }
+    }
+
+    fn modified_orders(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        modified_orders: Vec<ModifiedOrder>,
+    ) {
+        for m in modified_orders.iter() {
+            let acc = self.entry(m.id.order_book_id, timestamp);
+            match m.modify_type {
+                ModifyType::ReduceQty => acc.reduce_qty += 1,
+                ModifyType::PriceChange => acc.price_changes += 1,
+                ModifyType::Both => {
+                    acc.reduce_qty += 1;
+                    acc.price_changes += 1;
+                }
+                ModifyType::Neither => (),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_order_statistics() {
+    use crate::{
+        replay,
+        Session,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        // day session
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),3,FUT_NK225M_2109(590334),S,2,1,290050000,0,2",
+        "D,2021-03-01T00:00:01.050000000(1614556801050000000),3,FUT_NK225M_2109(590334),S",
+        "D,2021-03-01T00:00:02.000000000(1614556802000000000),1,FUT_NK225M_2109(590334),B",
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),1,FUT_NK225M_2109(590334),B,1,3,289850000,0,2",
+        "E,2021-03-01T00:00:04.000000000(1614556804000000000),2,FUT_NK225M_2109(590334),S,5,100,0,,",
+        // leg price of a combination trade
+        "P,2021-03-01T00:00:05.000000000(1614556805000000000),300,7,B,2,FUT_NK225M_2109(590334),290000000,,,,N",
+        // night session
+        "D,2021-03-01T08:00:00.000000000(1614585600000000000),1,FUT_NK225M_2109(590334),B",
+    ];
+
+    let mut aggregator = OrderStatisticsAggregator::default();
+    replay(&file, &mut aggregator);
+    let stats = aggregator.statistics();
+    assert_eq!(stats.len(), 2);
+
+    let day = &stats[0];
+    assert_eq!(day.session.session, Session::Day);
+    assert_eq!((day.adds, day.cancels, day.fills), (3, 1, 1));
+    assert_eq!(day.filled_qty, 5);
+    assert_eq!((day.reduce_qty, day.price_changes), (1, 1));
+    assert_eq!(day.cancel_to_trade_ratio, Some(1.0));
+    assert_eq!(day.fast_cancel_share, Some(1.0 / 3.0));
+    // 50ms and 3s
+    assert_eq!(day.median_lifetime, Some(Duration::milliseconds(1525)));
+
+    let night = &stats[1];
+    assert_eq!(night.session.session, Session::Night);
+    assert_eq!((night.adds, night.cancels, night.fills), (0, 1, 0));
+    assert_eq!(night.cancel_to_trade_ratio, None);
+    assert_eq!(night.fast_cancel_share, None);
+}
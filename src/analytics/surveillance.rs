@@ -0,0 +1,492 @@
+use std::collections::{
+    HashMap,
+    VecDeque,
+};
+
+use chrono::{
+    Duration,
+    NaiveDateTime,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::callback_datatype::{
+    CTagWithCorrespondingPTag,
+    Created,
+    ModifiedOrder,
+    OrderDeletion,
+    OrderExecution,
+    Trade,
+};
+use crate::{
+    impl_composite_callback,
+    AddOrder,
+    Children,
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    Side,
+    UniqueId,
+};
+
+/// pattern found by a `Detector`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Alert {
+    /// `Detector::name`
+    pub detector: String,
+    pub order_book_id: i64,
+    pub timestamp: NaiveDateTime,
+    /// orders involved in the pattern
+    pub order_ids: Vec<UniqueId>,
+    pub description: String,
+}
+
+/// A runtime callback looking for a pattern. Run detectors with `SurveillanceRunner`.
+pub trait Detector: OrderBookRunTimeCallback {
+    fn name(&self) -> &str;
+
+    /// alerts raised since the last call
+    fn drain_alerts(&mut self) -> Vec<Alert>;
+}
+
+/// Runs detectors on the same runtime and collects their alerts.
+/// Alerts are collected after each message stack, in the order of `detectors`.
+#[derive(Default)]
+pub struct SurveillanceRunner {
+    pub detectors: Vec<Box<dyn Detector>>,
+    alerts: Vec<Alert>,
+}
+
+impl SurveillanceRunner {
+    pub fn new(detectors: Vec<Box<dyn Detector>>) -> Self {
+        Self {
+            detectors,
+            alerts: vec![],
+        }
+    }
+
+    pub fn with_detector(mut self, detector: impl Detector + 'static) -> Self {
+        self.detectors.push(Box::new(detector));
+        self
+    }
+
+    pub fn alerts(&self) -> &[Alert] {
+        &self.alerts
+    }
+
+    pub fn into_alerts(self) -> Vec<Alert> {
+        self.alerts
+    }
+
+    fn collect(&mut self) {
+        for detector in self.detectors.iter_mut() {
+            self.alerts.extend(detector.drain_alerts());
+        }
+    }
+}
+
+impl Children for SurveillanceRunner {
+    fn for_each_child(&mut self, mut f: impl FnMut(&mut dyn OrderBookRunTimeCallback)) {
+        for detector in self.detectors.iter_mut() {
+            f(detector);
+        }
+    }
+
+    fn after_event(&mut self) {
+        self.collect();
+    }
+}
+
+impl_composite_callback!(impl<> for SurveillanceRunner);
+
+/// Flags a large order placed away from the touch, cancelled shortly after an order on the
+/// opposite side was filled.
+///
+/// The alert references the cancelled order first, then the filled orders.
+pub struct AwayFromTouchCancelDetector {
+    /// orders with at least this quantity are tracked
+    pub min_qty: i64,
+    /// orders at least this many ticks away from the best price of the same side are tracked.
+    /// The best price is the one before the message stack adding the order
+    pub min_ticks_away: f64,
+    /// the cancel has to follow the opposite fill within this time
+    pub max_delay: Duration,
+    /// tracked orders
+    watched: HashMap<UniqueId, AddOrder>,
+    /// (order_book_id, side) => best price before the current message stack,
+    /// for the sides with `A` tags of at least `min_qty`
+    touch: HashMap<(i64, Side), Option<i64>>,
+    /// (order_book_id, side of the filled orders) => (time, filled orders) of the last fill
+    last_fill: HashMap<(i64, Side), (NaiveDateTime, Vec<UniqueId>)>,
+    alerts: Vec<Alert>,
+}
+
+impl AwayFromTouchCancelDetector {
+    pub fn new(min_qty: i64, min_ticks_away: f64, max_delay: Duration) -> Self {
+        Self {
+            min_qty,
+            min_ticks_away,
+            max_delay,
+            watched: HashMap::new(),
+            touch: HashMap::new(),
+            last_fill: HashMap::new(),
+            alerts: vec![],
+        }
+    }
+}
+
+impl Detector for AwayFromTouchCancelDetector {
+    fn name(&self) -> &str {
+        "away_from_touch_cancel"
+    }
+
+    fn drain_alerts(&mut self) -> Vec<Alert> {
+        std::mem::take(&mut self.alerts)
+    }
+}
+
+impl OrderBookRunTimeCallback for AwayFromTouchCancelDetector {
+    fn event_start(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        self.touch.clear();
+        for msg in stack {
+            let MessageEnum::AddOrder(add) = msg else {
+                continue;
+            };
+            if add.quantity < self.min_qty {
+                continue;
+            }
+            let Some(book) = order_book_map.get(&add.order_book_id) else {
+                continue;
+            };
+            self.touch
+                .entry((add.order_book_id, add.side))
+                .or_insert_with(|| {
+                    match add.side {
+                        Side::Buy => book.best_bid(),
+                        Side::Sell => book.best_ask(),
+                    }
+                    .map(|best| best.price)
+                });
+        }
+    }
+
+    fn created(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        created: Created,
+    ) {
+        for add in created.msgs.iter() {
+            if add.quantity < self.min_qty {
+                continue;
+            }
+            let Some(book) = order_book_map.get(&add.order_book_id) else {
+                continue;
+            };
+            // the message stack may have moved the touch
+            let best = self
+                .touch
+                .get(&(add.order_book_id, add.side))
+                .copied()
+                .flatten();
+            let away = best
+                .and_then(|best| book.ticks_between(add.price as f64, best as f64))
+                .map(|ticks| ticks >= self.min_ticks_away)
+                .unwrap_or(false);
+            if away {
+                self.watched.insert(UniqueId::from_add_order(add), *add);
+            }
+        }
+    }
+
+    fn trades(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        trades: Vec<Trade>,
+    ) {
+        for trade in trades {
+            for id in trade.passive_orders.iter() {
+                let entry = self
+                    .last_fill
+                    .entry((trade.order_book_id, id.side))
+                    .or_insert_with(|| (trade.timestamp, vec![]));
+                if entry.0 != trade.timestamp {
+                    *entry = (trade.timestamp, vec![]);
+                }
+                entry.1.push(*id);
+            }
+        }
+    }
+
+    fn deletions(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        deletion: Vec<OrderDeletion>,
+    ) {
+        for d in deletion.iter() {
+            let id = UniqueId::from_delete_order(&d.msg);
+            let Some(add) = self.watched.remove(&id) else {
+                continue;
+            };
+            let opposite = Side::from(!id.side.is_buy());
+            let Some((filled_at, filled)) = self.last_fill.get(&(id.order_book_id, opposite))
+            else {
+                continue;
+            };
+            if *filled_at < add.timestamp || *timestamp - *filled_at > self.max_delay {
+                continue;
+            }
+            let mut order_ids = vec![id];
+            order_ids.extend(filled.iter().copied());
+            self.alerts.push(Alert {
+                detector: self.name().to_string(),
+                order_book_id: id.order_book_id,
+                timestamp: *timestamp,
+                order_ids,
+                description: format!(
+                    "{:?} order of {} at {} cancelled {}ms after a fill on the opposite side",
+                    id.side,
+                    add.quantity,
+                    add.price,
+                    (*timestamp - *filled_at).num_milliseconds()
+                ),
+            });
+        }
+    }
+
+    fn modified_orders(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        modified_orders: Vec<ModifiedOrder>,
+    ) {
+        for m in modified_orders.iter() {
+            if let Some(watched) = self.watched.get_mut(&m.id) {
+                watched.price = m.modify_msg.price;
+                watched.quantity = m.modify_msg.quantity;
+            }
+        }
+    }
+
+    fn executions(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        executions: Vec<OrderExecution>,
+    ) {
+        // filled orders are not cancelled
+        for e in executions.iter() {
+            let add = &e.matched_order_after_execution;
+            if add.quantity == 0 {
+                self.watched.remove(&UniqueId::from_add_order(add));
+            }
+        }
+    }
+
+    fn ctag_execution(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        executed_with_price_info: Vec<CTagWithCorrespondingPTag>,
+    ) {
+        for group in executed_with_price_info.iter() {
+            for add in group.matched_add_order.iter() {
+                if add.quantity == 0 {
+                    self.watched.remove(&UniqueId::from_add_order(add));
+                }
+            }
+        }
+    }
+}
+
+/// Flags rapid add and cancel at the same price.
+///
+/// An alert is raised when at least `min_cycles` orders were added and cancelled
+/// at the same order book, side and price within `window`. The history of the price is cleared after the alert.
+pub struct AddCancelBurstDetector {
+    pub min_cycles: usize,
+    pub window: Duration,
+    /// creation time of orders on the book
+    created_at: HashMap<UniqueId, NaiveDateTime>,
+    /// (order_book_id, side, price) => (cancel time, order) of the orders added and cancelled within the window
+    cycles: HashMap<(i64, Side, i64), VecDeque<(NaiveDateTime, UniqueId)>>,
+    alerts: Vec<Alert>,
+}
+
+impl AddCancelBurstDetector {
+    pub fn new(min_cycles: usize, window: Duration) -> Self {
+        Self {
+            min_cycles,
+            window,
+            created_at: HashMap::new(),
+            cycles: HashMap::new(),
+            alerts: vec![],
+        }
+    }
+}
+
+impl Detector for AddCancelBurstDetector {
+    fn name(&self) -> &str {
+        "add_cancel_burst"
+    }
+
+    fn drain_alerts(&mut self) -> Vec<Alert> {
+        std::mem::take(&mut self.alerts)
+    }
+}
+
+impl OrderBookRunTimeCallback for AddCancelBurstDetector {
+    fn created(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        created: Created,
+    ) {
+        for add in created.msgs.iter() {
+            self.created_at
+                .insert(UniqueId::from_add_order(add), add.timestamp);
+        }
+    }
+
+    fn executions(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        executions: Vec<OrderExecution>,
+    ) {
+        for e in executions.iter() {
+            let add = &e.matched_order_after_execution;
+            if add.quantity == 0 {
+                self.created_at.remove(&UniqueId::from_add_order(add));
+            }
+        }
+    }
+
+    fn ctag_execution(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        executed_with_price_info: Vec<CTagWithCorrespondingPTag>,
+    ) {
+        for group in executed_with_price_info.iter() {
+            for add in group.matched_add_order.iter() {
+                if add.quantity == 0 {
+                    self.created_at.remove(&UniqueId::from_add_order(add));
+                }
+            }
+        }
+    }
+
+    fn deletions(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        deletion: Vec<OrderDeletion>,
+    ) {
+        for d in deletion.iter() {
+            let id = UniqueId::from_delete_order(&d.msg);
+            let Some(created_at) = self.created_at.remove(&id) else {
+                continue;
+            };
+            if *timestamp - created_at > self.window {
+                continue;
+            }
+            let key = (id.order_book_id, id.side, d.deleted_order.price);
+            let cycles = self.cycles.entry(key).or_default();
+            cycles.push_back((*timestamp, id));
+            while let Some((t, _)) = cycles.front() {
+                if *timestamp - *t > self.window {
+                    cycles.pop_front();
+                } else {
+                    break;
+                }
+            }
+            if cycles.len() >= self.min_cycles {
+                let order_ids: Vec<UniqueId> = cycles.drain(..).map(|(_, id)| id).collect();
+                self.alerts.push(Alert {
+                    detector: self.name().to_string(),
+                    order_book_id: id.order_book_id,
+                    timestamp: *timestamp,
+                    description: format!(
+                        "{} {:?} orders added and cancelled at {} within {}ms",
+                        order_ids.len(),
+                        id.side,
+                        d.deleted_order.price,
+                        self.window.num_milliseconds()
+                    ),
+                    order_ids,
+                });
+            }
+        }
+    }
+}
+
+#[test]
+fn test_surveillance() {
+    use crate::{
+        replay,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "L,2021-02-28T21:07:50.931282000(1614546470931282000),FUT_NK225M_2109(590334),50000,0,999999999",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,1,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S,1,1,290000000,0,2",
+        // large bid 3 ticks away
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),3,FUT_NK225M_2109(590334),B,2,100,289750000,0,2",
+        "E,2021-03-01T00:00:03.000000000(1614556803000000000),2,FUT_NK225M_2109(590334),S,1,100,0,,",
+        "D,2021-03-01T00:00:03.200000000(1614556803200000000),3,FUT_NK225M_2109(590334),B",
+        // burst at the same price
+        "A,2021-03-01T00:00:04.000000000(1614556804000000000),4,FUT_NK225M_2109(590334),S,1,1,290050000,0,2",
+        "D,2021-03-01T00:00:04.010000000(1614556804010000000),4,FUT_NK225M_2109(590334),S",
+        "A,2021-03-01T00:00:04.020000000(1614556804020000000),5,FUT_NK225M_2109(590334),S,1,1,290050000,0,2",
+        "D,2021-03-01T00:00:04.030000000(1614556804030000000),5,FUT_NK225M_2109(590334),S",
+        "A,2021-03-01T00:00:04.040000000(1614556804040000000),6,FUT_NK225M_2109(590334),S,1,1,290050000,0,2",
+        "D,2021-03-01T00:00:04.050000000(1614556804050000000),6,FUT_NK225M_2109(590334),S",
+        // large bid 1 tick away from the touch before the stack, 2 ticks away after it
+        "A,2021-03-01T00:00:05.000000000(1614556805000000000),7,FUT_NK225M_2109(590334),B,1,1,289950000,0,2",
+        "A,2021-03-01T00:00:05.000000000(1614556805000000000),8,FUT_NK225M_2109(590334),B,3,100,289850000,0,2",
+        "A,2021-03-01T00:00:05.000000000(1614556805000000000),9,FUT_NK225M_2109(590334),S,1,1,290000000,0,2",
+        "E,2021-03-01T00:00:06.000000000(1614556806000000000),9,FUT_NK225M_2109(590334),S,1,102,0,,",
+        "D,2021-03-01T00:00:06.200000000(1614556806200000000),8,FUT_NK225M_2109(590334),B",
+    ];
+
+    let mut runner = SurveillanceRunner::default()
+        .with_detector(AwayFromTouchCancelDetector::new(
+            50,
+            2.0,
+            Duration::milliseconds(500),
+        ))
+        .with_detector(AddCancelBurstDetector::new(3, Duration::seconds(1)));
+    replay(&file, &mut runner);
+
+    let alerts = runner.into_alerts();
+    assert_eq!(alerts.len(), 2);
+    assert_eq!(alerts[0].detector, "away_from_touch_cancel");
+    let id = |order_id, side| {
+        UniqueId {
+            order_book_id: 590334,
+            order_id,
+            side,
+        }
+    };
+    assert_eq!(
+        alerts[0].order_ids,
+        vec![id(3, Side::Buy), id(2, Side::Sell)]
+    );
+    assert_eq!(alerts[1].detector, "add_cancel_burst");
+    assert_eq!(
+        alerts[1].order_ids,
+        vec![id(4, Side::Sell), id(5, Side::Sell), id(6, Side::Sell)]
+    );
+}
@@ -0,0 +1,577 @@
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+    HashMap,
+    HashSet,
+};
+
+use chrono::{
+    Duration,
+    NaiveDateTime,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::black76::{
+    Black76Config,
+    OptionAnalytics,
+};
+use crate::{
+    FinancialProduct,
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    PutOrCall,
+};
+
+/// Implied volatilities of options on the same forward at a timestamp, grouped by expiry.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VolSurface {
+    pub timestamp: NaiveDateTime,
+    pub forward_order_book_id: i64,
+    /// ascending order of expiration date
+    pub slices: Vec<VolSlice>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VolSlice {
+    pub expiration_date: i64,
+    pub time_to_expiry: f64,
+    pub forward: f64,
+    /// ascending order of strike
+    pub points: Vec<VolPoint>,
+    /// None if there were not enough points to fit
+    pub svi: Option<SviParams>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct VolPoint {
+    pub order_book_id: i64,
+    pub put_or_call: PutOrCall,
+    pub strike: f64,
+    /// ln(strike / forward)
+    pub log_moneyness: f64,
+    pub bid_iv: Option<f64>,
+    pub ask_iv: Option<f64>,
+    pub mid_iv: f64,
+}
+
+/// Raw SVI parametrisation of total implied variance.
+///
+/// w(k) = a + b * (rho * (k - m) + sqrt((k - m)^2 + sigma^2)), where k is log moneyness.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct SviParams {
+    pub a: f64,
+    pub b: f64,
+    pub rho: f64,
+    pub m: f64,
+    pub sigma: f64,
+    /// root mean squared error of the fit in implied volatility
+    pub rmse: f64,
+}
+
+impl SviParams {
+    pub fn total_variance(&self, log_moneyness: f64) -> f64 {
+        let x = log_moneyness - self.m;
+        self.a + self.b * (self.rho * x + (x * x + self.sigma * self.sigma).sqrt())
+    }
+
+    /// implied volatility at `log_moneyness`. None if the total variance is negative.
+    pub fn implied_vol(&self, log_moneyness: f64, time_to_expiry: f64) -> Option<f64> {
+        let w = self.total_variance(log_moneyness);
+        if w < 0.0 || time_to_expiry <= 0.0 {
+            None
+        } else {
+            Some((w / time_to_expiry).sqrt())
+        }
+    }
+
+    /// Fits SVI to `(log_moneyness, total_variance)`.
+    ///
+    /// Uses quasi-explicit method: for fixed `m` and `sigma` the rest is linear least squares,
+    /// `m` and `sigma` are searched on a grid and refined with a pattern search.
+    /// returns None if there are less than 5 points.
+    pub fn fit(points: &[(f64, f64)], time_to_expiry: f64) -> Option<Self> {
+        if points.len() < 5 || time_to_expiry <= 0.0 {
+            return None;
+        }
+        let (k_min, k_max) = points
+            .iter()
+            .fold((f64::MAX, f64::MIN), |(lo, hi), (k, _)| {
+                (lo.min(*k), hi.max(*k))
+            });
+        let width = (k_max - k_min).max(1e-4);
+
+        // (a, d, c, m, sigma, squared error)
+        type Candidate = (f64, f64, f64, f64, f64, f64);
+        fn consider(
+            best: &mut Option<Candidate>,
+            points: &[(f64, f64)],
+            m: f64,
+            sigma: f64,
+        ) -> bool {
+            match svi_linear_fit(points, m, sigma) {
+                Some((a, d, c, err)) if best.map(|b| err < b.5).unwrap_or(true) => {
+                    best.replace((a, d, c, m, sigma, err));
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        let mut best = None;
+        for i in 0..=20 {
+            let m = k_min + width * i as f64 / 20.0;
+            for j in 0..20 {
+                let sigma = width * 1e-2 * 10f64.powf(j as f64 / 19.0 * 2.5);
+                consider(&mut best, points, m, sigma);
+            }
+        }
+
+        // pattern search around the best grid point
+        let (_, _, _, mut m, mut sigma, _) = best?;
+        let (mut step_m, mut step_s) = (width / 20.0, sigma / 2.0);
+        for _ in 0..500 {
+            let improved = [(step_m, 0.0), (-step_m, 0.0), (0.0, step_s), (0.0, -step_s)]
+                .into_iter()
+                .find(|(dm, ds)| {
+                    sigma + ds > 0.0 && consider(&mut best, points, m + dm, sigma + ds)
+                });
+            match improved {
+                Some((dm, ds)) => {
+                    m += dm;
+                    sigma += ds;
+                }
+                None => {
+                    step_m /= 2.0;
+                    step_s /= 2.0;
+                    if step_m < 1e-10 && step_s < 1e-10 {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let (a, d, c, m, sigma, _) = best?;
+        let mut params = SviParams {
+            a,
+            b: c / sigma,
+            rho: if c > 0.0 { d / c } else { 0.0 },
+            m,
+            sigma,
+            rmse: 0.0,
+        };
+        let sq_err = points.iter().fold(0.0, |acc, (k, w)| {
+            let fitted = params.implied_vol(*k, time_to_expiry).unwrap_or(0.0);
+            let actual = (w.max(0.0) / time_to_expiry).sqrt();
+            acc + (fitted - actual).powi(2)
+        });
+        params.rmse = (sq_err / points.len() as f64).sqrt();
+        Some(params)
+    }
+}
+
+/// least squares of w = a + d * y + c * sqrt(y^2 + 1), y = (k - m) / sigma.
+/// returns `(a, d, c, squared error)`, None if the parameters violate `c >= 0` and `|d| <= c`.
+fn svi_linear_fit(points: &[(f64, f64)], m: f64, sigma: f64) -> Option<(f64, f64, f64, f64)> {
+    let mut ata = [[0.0; 3]; 3];
+    let mut atb = [0.0; 3];
+    for (k, w) in points {
+        let y = (k - m) / sigma;
+        let row = [1.0, y, (y * y + 1.0).sqrt()];
+        for i in 0..3 {
+            for j in 0..3 {
+                ata[i][j] += row[i] * row[j];
+            }
+            atb[i] += row[i] * w;
+        }
+    }
+    let [a, d, c] = solve3(ata, atb)?;
+    if c < 0.0 || d.abs() > c {
+        return None;
+    }
+    let err = points.iter().fold(0.0, |acc, (k, w)| {
+        let y = (k - m) / sigma;
+        acc + (a + d * y + c * (y * y + 1.0).sqrt() - w).powi(2)
+    });
+    Some((a, d, c, err))
+}
+
+/// solves 3x3 linear system with gaussian elimination
+fn solve3(mut a: [[f64; 3]; 3], mut b: [f64; 3]) -> Option<[f64; 3]> {
+    for col in 0..3 {
+        let pivot = (col..3).max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))?;
+        if a[pivot][col].abs() < 1e-14 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in (col + 1)..3 {
+            let f = a[row][col] / a[col][col];
+            let pivot_row = a[col];
+            for (x, p) in a[row].iter_mut().zip(pivot_row).skip(col) {
+                *x -= f * p;
+            }
+            b[row] -= f * b[col];
+        }
+    }
+    let mut x = [0.0; 3];
+    for row in (0..3).rev() {
+        let s = ((row + 1)..3).fold(b[row], |acc, k| acc - a[row][k] * x[k]);
+        x[row] = s / a[row][row];
+    }
+    Some(x)
+}
+
+/// Parameters for building `VolSurface`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VolSurfaceConfig {
+    pub black76: Black76Config,
+    /// quotes whose order book did not change for longer than this are dropped. None keeps every quote.
+    pub max_quote_age: Option<Duration>,
+    /// uses puts below the forward and calls above it
+    pub otm_only: bool,
+}
+
+impl Default for VolSurfaceConfig {
+    fn default() -> Self {
+        Self {
+            black76: Black76Config::default(),
+            max_quote_age: None,
+            otm_only: true,
+        }
+    }
+}
+
+impl VolSurfaceConfig {
+    /// returns a point if the quote is two sided, not crossed, not stale and has mid implied volatility
+    fn point(
+        &self,
+        analytics: &OptionAnalytics,
+        last_update: Option<&NaiveDateTime>,
+    ) -> Option<VolPoint> {
+        let (bid, ask) = analytics.bid.zip(analytics.ask)?;
+        if bid >= ask {
+            return None;
+        }
+        // a book missing from `last_update` is not known to have changed, so it is stale
+        if let Some(max_age) = self.max_quote_age {
+            if last_update.is_none_or(|t| analytics.timestamp - *t > max_age) {
+                return None;
+            }
+        }
+        let is_otm = match analytics.put_or_call {
+            PutOrCall::Put => analytics.strike < analytics.forward,
+            _ => analytics.strike >= analytics.forward,
+        };
+        if self.otm_only && !is_otm {
+            return None;
+        }
+        Some(VolPoint {
+            order_book_id: analytics.order_book_id,
+            put_or_call: analytics.put_or_call,
+            strike: analytics.strike,
+            log_moneyness: (analytics.strike / analytics.forward).ln(),
+            bid_iv: analytics.bid_iv,
+            ask_iv: analytics.ask_iv,
+            mid_iv: analytics.mid_iv?,
+        })
+    }
+
+    /// builds surfaces from the current state of the books, one for each forward.
+    ///
+    /// `last_update` is the last time each order book changed, used to filter stale quotes.
+    /// With `max_quote_age`, the quotes of books missing from it are dropped as stale.
+    pub fn build<'a>(
+        &self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        option_order_book_ids: impl IntoIterator<Item = &'a i64>,
+        last_update: &HashMap<i64, NaiveDateTime>,
+        timestamp: &NaiveDateTime,
+    ) -> Vec<VolSurface> {
+        // forward => expiration_date => slice
+        let mut map: BTreeMap<i64, BTreeMap<i64, VolSlice>> = BTreeMap::new();
+        for id in option_order_book_ids {
+            let Some(analytics) = self.black76.analyze(order_book_map, *id, timestamp) else {
+                continue;
+            };
+            let Some(point) = self.point(&analytics, last_update.get(id)) else {
+                continue;
+            };
+            map.entry(analytics.forward_order_book_id)
+                .or_default()
+                .entry(analytics.expiration_date)
+                .or_insert_with(|| {
+                    VolSlice {
+                        expiration_date: analytics.expiration_date,
+                        time_to_expiry: analytics.time_to_expiry,
+                        forward: analytics.forward,
+                        points: vec![],
+                        svi: None,
+                    }
+                })
+                .points
+                .push(point);
+        }
+
+        map.into_iter()
+            .map(|(forward_order_book_id, expiries)| {
+                let mut slices: Vec<VolSlice> = expiries.into_values().collect();
+                for slice in slices.iter_mut() {
+                    slice.points.sort_by(|a, b| {
+                        a.strike
+                            .total_cmp(&b.strike)
+                            .then(a.order_book_id.cmp(&b.order_book_id))
+                    });
+                    let variances: Vec<(f64, f64)> = slice
+                        .points
+                        .iter()
+                        .map(|p| (p.log_moneyness, p.mid_iv * p.mid_iv * slice.time_to_expiry))
+                        .collect();
+                    slice.svi = SviParams::fit(&variances, slice.time_to_expiry);
+                }
+                VolSurface {
+                    timestamp: *timestamp,
+                    forward_order_book_id,
+                    slices,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Calls `callback` with `VolSurface`s every `interval` of the replay.
+///
+/// Snapshots are taken at the end of the first event at or after each interval boundary.
+pub struct VolSurfaceRecorder<F>
+where
+    F: FnMut(&VolSurface),
+{
+    pub config: VolSurfaceConfig,
+    pub interval: Duration,
+    callback: F,
+    options: BTreeSet<i64>,
+    last_update: HashMap<i64, NaiveDateTime>,
+    next_snapshot: Option<NaiveDateTime>,
+}
+
+impl<F> VolSurfaceRecorder<F>
+where
+    F: FnMut(&VolSurface),
+{
+    /// panics if `interval` is not positive
+    pub fn new(config: VolSurfaceConfig, interval: Duration, callback: F) -> Self {
+        assert!(
+            interval > Duration::zero(),
+            "snapshot interval must be positive: {:?}",
+            interval
+        );
+        Self {
+            config,
+            interval,
+            callback,
+            options: BTreeSet::new(),
+            last_update: HashMap::new(),
+            next_snapshot: None,
+        }
+    }
+}
+
+impl<F> OrderBookRunTimeCallback for VolSurfaceRecorder<F>
+where
+    F: FnMut(&VolSurface),
+{
+    fn event_start(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        for msg in stack {
+            if let MessageEnum::ProductInfo(info) = msg {
+                if info.financial_product == FinancialProduct::Option
+                    && !info.put_or_call.is_combo()
+                {
+                    self.options.insert(info.order_book_id);
+                }
+            }
+        }
+    }
+
+    fn order_book_id_with_changes(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        changes: &HashSet<i64>,
+    ) {
+        for id in changes {
+            self.last_update.insert(*id, *timestamp);
+        }
+    }
+
+    fn event_end(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        _stack: &[MessageEnum],
+    ) {
+        let next = *self.next_snapshot.get_or_insert(*timestamp);
+        if *timestamp < next {
+            return;
+        }
+
+        let surfaces = self.config.build(
+            order_book_map,
+            self.options.iter(),
+            &self.last_update,
+            timestamp,
+        );
+        for surface in surfaces.iter() {
+            (self.callback)(surface);
+        }
+
+        let mut next = next + self.interval;
+        while next <= *timestamp {
+            next += self.interval;
+        }
+        self.next_snapshot.replace(next);
+    }
+}
+
+#[test]
+fn test_svi_fit() {
+    let t = 0.25;
+    let truth = SviParams {
+        a: 0.002,
+        b: 0.05,
+        rho: -0.4,
+        m: 0.01,
+        sigma: 0.1,
+        rmse: 0.0,
+    };
+    let points: Vec<(f64, f64)> = (-10..=10)
+        .map(|i| {
+            let k = i as f64 * 0.02;
+            (k, truth.total_variance(k))
+        })
+        .collect();
+    let fitted = SviParams::fit(&points, t).unwrap();
+    assert!(fitted.rmse < 1e-4, "{fitted:?}");
+    for (k, w) in points {
+        assert!((fitted.total_variance(k) - w).abs() < 1e-5);
+    }
+
+    assert_eq!(SviParams::fit(&[(0.0, 0.01); 4], t), None);
+}
+
+#[test]
+#[should_panic]
+fn test_vol_surface_recorder_zero_interval() {
+    VolSurfaceRecorder::new(VolSurfaceConfig::default(), Duration::zero(), |_| {});
+}
+
+#[test]
+fn test_vol_surface_recorder() {
+    use super::black76::black76_price;
+    use crate::{
+        replay,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),40632820,PUT_NK225_210910_28000,186098018,186098018,1,JPY,4,0,0,1,0,0,0,500,28000,20210910,0,2",
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),40632821,PUT_NK225_210910_27000,186098019,186098019,1,JPY,4,0,0,1,0,0,0,500,27000,20210910,0,2",
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),223347188,CAL_NK225_210910_29500,196099518,196099518,1,JPY,4,0,0,1,0,0,0,500,29500,20210910,0,1",
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),223347189,CAL_NK225_210910_30000,196099519,196099519,1,JPY,4,0,0,1,0,0,0,500,30000,20210910,0,1",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),2,FUT_NK225M_2109(590334),S,1,5,290100000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),3,PUT_NK225_210910_28000(40632820),B,1,1,11500000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),4,PUT_NK225_210910_28000(40632820),S,1,1,12000000,0,2",
+        // crossed
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),5,PUT_NK225_210910_27000(40632821),B,1,1,8000000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),6,PUT_NK225_210910_27000(40632821),S,1,1,7900000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),7,CAL_NK225_210910_29500(223347188),B,1,1,14000000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),8,CAL_NK225_210910_29500(223347188),S,1,1,14500000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),9,CAL_NK225_210910_30000(223347189),B,1,1,10000000,0,2",
+        "A,2021-03-01T00:00:00.000000000(1614556800000000000),10,CAL_NK225_210910_30000(223347189),S,1,1,10500000,0,2",
+        // the quote of the 30000 call gets stale
+        "A,2021-03-01T00:00:10.000000000(1614556810000000000),11,PUT_NK225_210910_28000(40632820),B,2,1,11500000,0,2",
+        "A,2021-03-01T00:00:10.000000000(1614556810000000000),12,CAL_NK225_210910_29500(223347188),S,2,1,14500000,0,2",
+    ];
+    let config = VolSurfaceConfig {
+        black76: Black76Config {
+            forward_order_book_ids: HashMap::from([(500, 590334)]),
+            ..Default::default()
+        },
+        max_quote_age: Some(Duration::seconds(5)),
+        otm_only: true,
+    };
+    let mut snapshots = vec![];
+    let mut recorder = VolSurfaceRecorder::new(
+        config.clone(),
+        Duration::seconds(5),
+        |surface: &VolSurface| snapshots.push(surface.clone()),
+    );
+    let map = replay(&file, &mut recorder);
+    let last_update = recorder.last_update.clone();
+    drop(recorder);
+
+    let ids = |surface: &VolSurface| -> Vec<i64> {
+        surface.slices[0]
+            .points
+            .iter()
+            .map(|p| p.order_book_id)
+            .collect()
+    };
+    let ts = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").unwrap();
+    // no surface before the quotes
+    assert_eq!(snapshots.len(), 2);
+    assert_eq!(snapshots[0].timestamp, ts("2021-03-01T00:00:00"));
+    assert_eq!(snapshots[0].forward_order_book_id, 590334);
+    assert_eq!(snapshots[0].slices.len(), 1);
+    let slice = &snapshots[0].slices[0];
+    assert_eq!(slice.expiration_date, 20210910);
+    assert_eq!(slice.forward, 29000.0);
+    assert_eq!(slice.svi, None);
+    assert_eq!(ids(&snapshots[0]), vec![40632820, 223347188, 223347189]);
+    let put = slice.points[0];
+    assert_eq!(put.put_or_call, PutOrCall::Put);
+    assert_eq!(put.strike, 28000.0);
+    let mid = black76_price(
+        PutOrCall::Put,
+        29000.0,
+        28000.0,
+        slice.time_to_expiry,
+        put.mid_iv,
+        0.0,
+    );
+    assert!((mid - 1175.0).abs() < 1e-6, "{mid}");
+
+    assert_eq!(snapshots[1].timestamp, ts("2021-03-01T00:00:10"));
+    assert_eq!(ids(&snapshots[1]), vec![40632820, 223347188]);
+
+    // build
+    let options = [40632820, 40632821, 223347188, 223347189];
+    let now = ts("2021-03-01T00:00:10");
+    let surfaces = config.build(&map, options.iter(), &last_update, &now);
+    assert_eq!(surfaces, snapshots[1..]);
+    let fresh = VolSurfaceConfig {
+        max_quote_age: None,
+        ..config.clone()
+    };
+    let surfaces = fresh.build(&map, options.iter(), &HashMap::new(), &now);
+    assert_eq!(ids(&surfaces[0]), vec![40632820, 223347188, 223347189]);
+    // books missing from `last_update` are stale
+    let surfaces = config.build(
+        &map,
+        options.iter(),
+        &HashMap::from([(40632820, now)]),
+        &now,
+    );
+    assert_eq!(ids(&surfaces[0]), vec![40632820]);
+    let surfaces = config.build(&map, options.iter(), &HashMap::new(), &now);
+    assert!(surfaces.is_empty());
+}
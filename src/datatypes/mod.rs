@@ -3,7 +3,10 @@
 pub(crate) mod from_btree;
 
 mod symbol;
-pub use symbol::Symbol;
+pub use symbol::{
+    Symbol,
+    SymbolParseError,
+};
 
 mod unique_id;
 pub use unique_id::UniqueId;
@@ -1,85 +1,312 @@
-use std::convert::Infallible;
+use std::fmt;
 use std::str::FromStr;
 
 use chrono::NaiveDate;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 
 use crate::{
     FinancialProduct,
     PutOrCall,
 };
 
-//PUT_8473_210909_3600
-//CAL_TOPIX_211210_2050
-//NK225M_2105
+// Symbols found on OSE MBO data
+//
+// options              PUT_NK225_210312_19250, CAL_TOPIX_211210_2050, PUT_8473_210909_3600
+// weekly options       CAL_NK225_210305W_30250
+// futures              FUT_NK225M_2109, FUT_NK225MC_2109, FUT_TOPIXM_2106, FUT_JGBL_2106, NK225M_2105
+// combos               FUT_NK225_2106/NK225_2109, FUT_NK225_2106/2109
+//
+// legs of a combo may omit the prefix and the product name, those are taken from the previous leg.
 
+/// Typed representation of `ProductInfo::symbol`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Symbol {
     pub financial_product: FinancialProduct,
     pub put_or_call: PutOrCall,
+    /// e.g. `NK225`, `NK225M`, `TOPIX`, `JGBL`, `8473`
     pub product_name: String,
+    /// 0 if it's not an option
     pub strike_price: i64,
+    /// number of digits after the decimal point in the strike price, if the symbol had one.
     pub number_of_decimals_in_strike_price: i64,
+    /// Symbols with contract month only (`yymm`) are set to the first day of the month.
+    /// See `contract_month_only`
     pub expiration: NaiveDate,
-    pub combo_with: Box<Option<Symbol>>,
+    /// true if the expiration on the symbol was `yymm`
+    pub contract_month_only: bool,
+    /// true if the expiration had `W` marker. e.g. `210305W`
+    pub weekly: bool,
+    /// legs of combination product. Empty if it's not a combination product.
+    pub legs: Vec<Symbol>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SymbolParseError {
+    Empty,
+    /// symbol did not match any of the known formats
+    UnknownFormat(String),
+    InvalidExpiration(String),
+    InvalidStrikePrice(String),
+}
+
+impl fmt::Display for SymbolParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolParseError::Empty => write!(f, "symbol is empty"),
+            SymbolParseError::UnknownFormat(s) => write!(f, "unknown symbol format: {s}"),
+            SymbolParseError::InvalidExpiration(s) => write!(f, "invalid expiration: {s}"),
+            SymbolParseError::InvalidStrikePrice(s) => write!(f, "invalid strike price: {s}"),
+        }
+    }
 }
 
+impl std::error::Error for SymbolParseError {}
+
 impl FromStr for Symbol {
-    type Err = Infallible;
+    type Err = SymbolParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // futures
-        let mut item = if s.contains("/") {
-            (&s[4..]).split("/").map(parse_item).collect()
-        } else {
-            vec![parse_item(s)]
-        };
-
-        if item.len() == 2 {
-            item[0].combo_with = Box::new(item.pop());
+        if s.is_empty() {
+            return Err(SymbolParseError::Empty);
+        }
+
+        if !s.contains('/') {
+            return parse_leg(s, None);
         }
-        Ok(item.pop().ok_or(()).unwrap())
+
+        let mut legs: Vec<Symbol> = vec![];
+        for part in s.split('/') {
+            let leg = parse_leg(part, legs.last())?;
+            legs.push(leg);
+        }
+
+        let first = &legs[0];
+        Ok(Symbol {
+            financial_product: FinancialProduct::Combo,
+            put_or_call: PutOrCall::Combo,
+            product_name: first.product_name.clone(),
+            strike_price: 0,
+            number_of_decimals_in_strike_price: 0,
+            expiration: first.expiration,
+            contract_month_only: first.contract_month_only,
+            weekly: first.weekly,
+            legs,
+        })
     }
 }
 
-fn parse_item(s: &str) -> Symbol {
-    let n = s.chars().filter(|c| *c == '_').count();
-    let mut iter = s.split("_");
-    match n {
-        1 => {
-            Symbol {
-                financial_product: FinancialProduct::Future,
-                product_name: iter.next().unwrap().to_string(),
-                expiration: {
-                    let s = iter.next().unwrap();
-                    NaiveDate::from_ymd(
-                        2000i32 + (&s[0..2]).parse::<i32>().unwrap(),
-                        s[3..].parse().unwrap(),
-                        1,
-                    )
-                },
-                put_or_call: PutOrCall::Combo,
-                strike_price: 0,
-                number_of_decimals_in_strike_price: 0,
-                combo_with: Box::new(None),
-            }
+impl Symbol {
+    pub fn is_combo(&self) -> bool {
+        !self.legs.is_empty()
+    }
+
+    pub fn is_option(&self) -> bool {
+        self.financial_product == FinancialProduct::Option
+    }
+
+    pub fn is_future(&self) -> bool {
+        self.financial_product == FinancialProduct::Future
+    }
+
+    /// second leg of a combination product.
+    ///
+    /// Combination symbols used to be parsed as their first leg with the second one in the `combo_with` field.
+    #[deprecated(note = "use `legs` instead")]
+    pub fn combo_with(&self) -> Option<&Symbol> {
+        self.legs.get(1)
+    }
+}
+
+/// parses a single leg.
+/// `previous` is the leg before this one when parsing a combo.
+fn parse_leg(s: &str, previous: Option<&Symbol>) -> Result<Symbol, SymbolParseError> {
+    let unknown = || SymbolParseError::UnknownFormat(s.to_string());
+    let tokens: Vec<&str> = s.split('_').collect();
+    if tokens.iter().any(|t| t.is_empty()) {
+        return Err(unknown());
+    }
+
+    let (financial_product, put_or_call, rest) = match tokens[0] {
+        "PUT" | "CAL" => {
+            let poc = tokens[0].parse().map_err(|_| unknown())?;
+            (FinancialProduct::Option, poc, &tokens[1..])
         }
-        4 => {
-            Symbol {
-                financial_product: FinancialProduct::Option,
-                put_or_call: iter.next().unwrap().to_string().parse().unwrap(),
-                product_name: iter.next().unwrap().to_string(),
-                expiration: {
-                    let s = iter.next().unwrap();
-                    NaiveDate::from_ymd(
-                        2000i32 + (&s[0..2]).parse::<i32>().unwrap(),
-                        s[3..].parse().unwrap(),
-                        1,
-                    )
-                },
-                strike_price: 0,
-                number_of_decimals_in_strike_price: 0,
-                combo_with: Box::new(None),
+        "FUT" => (FinancialProduct::Future, PutOrCall::Combo, &tokens[1..]),
+        _ => {
+            match previous {
+                Some(prev) => (prev.financial_product, prev.put_or_call, &tokens[..]),
+                // futures without prefix. e.g. NK225M_2105
+                None if tokens.len() == 2 => {
+                    (FinancialProduct::Future, PutOrCall::Combo, &tokens[..])
+                }
+                None => return Err(unknown()),
             }
         }
-        _ => unreachable!(),
+    };
+
+    let is_option = financial_product == FinancialProduct::Option;
+    // [product name] expiration [strike price]
+    let (product_name, expiration, strike) = match (is_option, rest) {
+        (true, [name, exp, strike]) => (Some(*name), *exp, Some(*strike)),
+        (true, [exp, strike]) if previous.is_some() => (None, *exp, Some(*strike)),
+        (false, [name, exp]) => (Some(*name), *exp, None),
+        (false, [exp]) if previous.is_some() => (None, *exp, None),
+        _ => return Err(unknown()),
+    };
+
+    let product_name = match (product_name, previous) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(prev)) => prev.product_name.clone(),
+        (None, None) => return Err(unknown()),
+    };
+
+    let (expiration, contract_month_only, weekly) = parse_expiration(expiration)?;
+    let (strike_price, number_of_decimals_in_strike_price) = match strike {
+        Some(strike) => parse_strike_price(strike)?,
+        None => (0, 0),
+    };
+
+    Ok(Symbol {
+        financial_product,
+        put_or_call,
+        product_name,
+        strike_price,
+        number_of_decimals_in_strike_price,
+        expiration,
+        contract_month_only,
+        weekly,
+        legs: vec![],
+    })
+}
+
+/// `yymm`, `yymmdd` or `yymmddW`
+fn parse_expiration(s: &str) -> Result<(NaiveDate, bool, bool), SymbolParseError> {
+    let err = || SymbolParseError::InvalidExpiration(s.to_string());
+    let (digits, weekly) = match s.strip_suffix('W') {
+        Some(digits) => (digits, true),
+        None => (s, false),
+    };
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(err());
+    }
+
+    let num = |range: std::ops::Range<usize>| digits[range].parse::<u32>().map_err(|_| err());
+    let (date, contract_month_only) = match (digits.len(), weekly) {
+        (4, false) => {
+            (
+                NaiveDate::from_ymd_opt(2000 + num(0..2)? as i32, num(2..4)?, 1),
+                true,
+            )
+        }
+        (6, _) => {
+            (
+                NaiveDate::from_ymd_opt(2000 + num(0..2)? as i32, num(2..4)?, num(4..6)?),
+                false,
+            )
+        }
+        _ => return Err(err()),
+    };
+
+    date.map(|d| (d, contract_month_only, weekly))
+        .ok_or_else(err)
+}
+
+/// returns strike price and number of decimals. `150.5` => `(1505, 1)`
+fn parse_strike_price(s: &str) -> Result<(i64, i64), SymbolParseError> {
+    let err = || SymbolParseError::InvalidStrikePrice(s.to_string());
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    if int.is_empty() || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+        return Err(err());
     }
+    let strike = format!("{int}{frac}").parse().map_err(|_| err())?;
+    Ok((strike, frac.len() as i64))
+}
+
+#[test]
+fn test_symbol() {
+    let ymd = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+
+    let s: Symbol = "PUT_NK225_210312_19250".parse().unwrap();
+    assert!(s.is_option());
+    assert_eq!(s.put_or_call, PutOrCall::Put);
+    assert_eq!(s.product_name, "NK225");
+    assert_eq!(s.strike_price, 19250);
+    assert_eq!(s.expiration, ymd(2021, 3, 12));
+    assert!(!s.weekly && !s.contract_month_only);
+
+    let s: Symbol = "CAL_NK225_210305W_30250".parse().unwrap();
+    assert_eq!(s.put_or_call, PutOrCall::Call);
+    assert_eq!(s.strike_price, 30250);
+    assert_eq!(s.expiration, ymd(2021, 3, 5));
+    assert!(s.weekly);
+
+    let s: Symbol = "PUT_8473_210909_3600".parse().unwrap();
+    assert_eq!(s.product_name, "8473");
+    assert_eq!(s.strike_price, 3600);
+
+    let s: Symbol = "CAL_JGBL_210507_150.5".parse().unwrap();
+    assert_eq!(s.strike_price, 1505);
+    assert_eq!(s.number_of_decimals_in_strike_price, 1);
+
+    for (symbol, name) in [
+        ("FUT_NK225M_2109", "NK225M"),
+        ("FUT_NK225MC_2109", "NK225MC"),
+        ("FUT_TOPIXM_2106", "TOPIXM"),
+        ("FUT_JGBL_2106", "JGBL"),
+        ("NK225M_2105", "NK225M"),
+    ] {
+        let s: Symbol = symbol.parse().unwrap();
+        assert!(s.is_future(), "{symbol}");
+        assert_eq!(s.product_name, name);
+        assert!(s.contract_month_only);
+        assert_eq!(s.expiration.format("%d").to_string(), "01");
+    }
+
+    for symbol in ["FUT_NK225_2106/NK225_2109", "FUT_NK225_2106/2109"] {
+        let s: Symbol = symbol.parse().unwrap();
+        assert!(s.is_combo());
+        assert_eq!(s.financial_product, FinancialProduct::Combo);
+        assert_eq!(s.legs.len(), 2);
+        assert!(s
+            .legs
+            .iter()
+            .all(|leg| leg.is_future() && leg.product_name == "NK225"));
+        assert_eq!(s.legs[1].expiration, ymd(2021, 9, 1));
+        #[allow(deprecated)]
+        let second = s.combo_with();
+        assert_eq!(second, Some(&s.legs[1]));
+    }
+
+    let s: Symbol = "CAL_NK225_210312_29000/210312_29500/PUT_NK225_210312_27000"
+        .parse()
+        .unwrap();
+    assert_eq!(s.legs.len(), 3);
+    assert_eq!(s.legs[1].put_or_call, PutOrCall::Call);
+    assert_eq!(s.legs[1].strike_price, 29500);
+    assert_eq!(s.legs[2].put_or_call, PutOrCall::Put);
+
+    assert_eq!("".parse::<Symbol>(), Err(SymbolParseError::Empty));
+    assert!(matches!(
+        "PUT_NK225_2103XX_19250".parse::<Symbol>(),
+        Err(SymbolParseError::InvalidExpiration(_))
+    ));
+    assert!(matches!(
+        "PUT_NK225_210312_ABC".parse::<Symbol>(),
+        Err(SymbolParseError::InvalidStrikePrice(_))
+    ));
+    assert!(matches!(
+        "PUT_NK225_211332_19250".parse::<Symbol>(),
+        Err(SymbolParseError::InvalidExpiration(_))
+    ));
+    assert!(matches!(
+        "NK225".parse::<Symbol>(),
+        Err(SymbolParseError::UnknownFormat(_))
+    ));
+    assert!(matches!(
+        "FUT_NK225_2106/".parse::<Symbol>(),
+        Err(SymbolParseError::UnknownFormat(_))
+    ));
 }
@@ -1,6 +1,9 @@
 use std::convert::Infallible;
 
-use serde::Serialize;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 
 use crate::{
     AddOrder,
@@ -9,7 +12,7 @@ use crate::{
 };
 
 /// Orders are uniquely identified by its order_id, order_book_id and it's side (Buy Or Sell)
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Clone, Copy, Default)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize, Clone, Copy, Default)]
 pub struct UniqueId {
     pub order_book_id: i64,
     pub order_id: i64,
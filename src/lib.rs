@@ -4,3 +4,6 @@ pub use orderbook::*;
 
 mod datatypes;
 pub use datatypes::*;
+
+mod analytics;
+pub use analytics::*;
@@ -0,0 +1,145 @@
+use crate::callback_datatype::{
+    AggressorSide,
+    Trade,
+    TradeSource,
+};
+use crate::{
+    AddOrder,
+    MessageEnum,
+    Side,
+};
+
+/// Infers the aggressor side of trades reconstructed from a single message stack.
+///
+/// - trades in auctions are `AggressorSide::Auction`
+/// - leg prices of combination trades (`P` tag) are `AggressorSide::Unknown`,
+///   the orders of the leg book did not take part in them
+/// - when only one side of the match is reported, the aggressor is on the other side of the passive order.
+///   (`E` tag and `C` tag during continuous trading)
+/// - otherwise, `A` tag on the same order book in the same stack whose price crosses the trade price is used.
+///   This is the fill-and-store case, the incoming order is partially filled and the rest is put on the book. See `Created::is_fas`.
+///   When no `A` tag, or `A` tags on both sides are found, it stays `AggressorSide::Unknown`.
+///
+/// `stack` must be the message stack the trades were reconstructed from.
+pub fn infer_aggressor_sides(trades: &mut [Trade], stack: &[MessageEnum]) {
+    let adds: Vec<&AddOrder> = stack
+        .iter()
+        .filter_map(|msg| {
+            match msg {
+                MessageEnum::AddOrder(add) => Some(&**add),
+                _ => None,
+            }
+        })
+        .collect();
+
+    for trade in trades.iter_mut() {
+        trade.aggressor_side = infer_aggressor_side(trade, &adds);
+    }
+}
+
+/// infers aggressor side of a single trade. See `infer_aggressor_sides`
+pub fn infer_aggressor_side(trade: &Trade, adds: &[&AddOrder]) -> AggressorSide {
+    if trade.occurred_at_cross {
+        return AggressorSide::Auction;
+    }
+    if trade.source == TradeSource::LegPrice {
+        return AggressorSide::Unknown;
+    }
+
+    let mut passive_sides = trade.passive_orders.iter().map(|i| i.side);
+    if let Some(side) = passive_sides.next() {
+        if passive_sides.all(|s| s == side) {
+            return AggressorSide::from_passive_side(side);
+        }
+    }
+
+    let crosses = |side: Side| {
+        adds.iter().any(|add| {
+            let is_passive = trade
+                .passive_orders
+                .iter()
+                .any(|id| id.order_id == add.order_id && id.side == add.side);
+            let price_check = match side {
+                Side::Buy => add.price >= trade.price,
+                Side::Sell => add.price <= trade.price,
+            };
+            add.order_book_id == trade.order_book_id
+                && add.side == side
+                && !is_passive
+                && price_check
+        })
+    };
+
+    match (crosses(Side::Buy), crosses(Side::Sell)) {
+        (true, false) => AggressorSide::Buy,
+        (false, true) => AggressorSide::Sell,
+        _ => AggressorSide::Unknown,
+    }
+}
+
+#[test]
+fn test_infer_aggressor_side() {
+    use crate::UniqueId;
+
+    let add = |order_id, side, price| {
+        MessageEnum::AddOrder(Box::new(AddOrder {
+            timestamp: Default::default(),
+            order_book_id: 1,
+            order_book_position: 1,
+            order_id,
+            price,
+            quantity: 1,
+            side,
+        }))
+    };
+    let id = |order_id, side| {
+        UniqueId {
+            order_book_id: 1,
+            order_id,
+            side,
+        }
+    };
+    let mut trade = Trade {
+        timestamp: Default::default(),
+        order_book_id: 1,
+        price: 100,
+        quantity: 1,
+        match_id: "1".to_string(),
+        combo_group_id: 0,
+        aggressor_side: AggressorSide::Unknown,
+        passive_orders: vec![id(10, Side::Buy), id(11, Side::Sell)],
+        occurred_at_cross: false,
+        source: TradeSource::ExecutionWithPriceInfo,
+    };
+
+    // fill and store: sell order crossing the trade price is put on the book
+    let stack = [add(12, Side::Sell, 90), add(13, Side::Buy, 80)];
+    let mut trades = [trade.clone()];
+    infer_aggressor_sides(&mut trades, &stack);
+    assert_eq!(trades[0].aggressor_side, AggressorSide::Sell);
+
+    // crossing orders on both sides
+    let stack = [add(12, Side::Sell, 90), add(13, Side::Buy, 110)];
+    let mut trades = [trade.clone()];
+    infer_aggressor_sides(&mut trades, &stack);
+    assert_eq!(trades[0].aggressor_side, AggressorSide::Unknown);
+
+    // leg price of a combination trade
+    let stack = [add(12, Side::Sell, 90)];
+    let mut trades = [Trade {
+        passive_orders: vec![],
+        source: TradeSource::LegPrice,
+        ..trade.clone()
+    }];
+    infer_aggressor_sides(&mut trades, &stack);
+    assert_eq!(trades[0].aggressor_side, AggressorSide::Unknown);
+
+    // passive side only
+    trade.passive_orders = vec![id(10, Side::Buy)];
+    let mut trades = [trade.clone()];
+    infer_aggressor_sides(&mut trades, &[]);
+    assert_eq!(trades[0].aggressor_side, AggressorSide::Sell);
+
+    trade.occurred_at_cross = true;
+    assert_eq!(infer_aggressor_side(&trade, &[]), AggressorSide::Auction);
+}
@@ -0,0 +1,208 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+
+use chrono::NaiveDateTime;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    PriceLevelView,
+    UniqueId,
+};
+
+/// what changed the top of book.
+/// When several kinds of messages touched the book in the same stack,
+/// the first one in `Trade`, `Modify`, `Cancel`, `Add` is used.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BboCause {
+    /// `A` tag
+    Add,
+    /// `D` tag
+    Cancel,
+    /// `E`, `C` or `P` tag
+    Trade,
+    /// `D` and `A` tag with the same order id
+    Modify,
+}
+
+impl BboCause {
+    fn priority(&self) -> u8 {
+        match self {
+            BboCause::Trade => 3,
+            BboCause::Modify => 2,
+            BboCause::Cancel => 1,
+            BboCause::Add => 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BboUpdate {
+    pub timestamp: NaiveDateTime,
+    pub order_book_id: i64,
+    pub best_bid: Option<PriceLevelView>,
+    pub best_ask: Option<PriceLevelView>,
+    pub cause: BboCause,
+}
+
+/// Passes `BboUpdate` to `f` only when the best bid, best ask or their quantity changed.
+/// Updates in the same message stack are passed in ascending order of order book id.
+pub struct BboTracker<F>
+where
+    F: FnMut(&BboUpdate),
+{
+    /// order book id => (best bid, best ask)
+    last: HashMap<i64, (Option<PriceLevelView>, Option<PriceLevelView>)>,
+    /// causes found in the current message stack
+    causes: HashMap<i64, BboCause>,
+    f: F,
+}
+
+impl<F> BboTracker<F>
+where
+    F: FnMut(&BboUpdate),
+{
+    pub fn new(f: F) -> Self {
+        Self {
+            last: HashMap::new(),
+            causes: HashMap::new(),
+            f,
+        }
+    }
+
+    /// last top of book passed to `f`
+    pub fn last_bbo(&self, order_book_id: i64) -> (Option<PriceLevelView>, Option<PriceLevelView>) {
+        self.last.get(&order_book_id).copied().unwrap_or_default()
+    }
+
+    fn insert_cause(&mut self, order_book_id: i64, cause: BboCause) {
+        let entry = self.causes.entry(order_book_id).or_insert(cause);
+        if cause.priority() > entry.priority() {
+            *entry = cause;
+        }
+    }
+}
+
+impl<F> OrderBookRunTimeCallback for BboTracker<F>
+where
+    F: FnMut(&BboUpdate),
+{
+    fn event_start(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        self.causes.clear();
+        let deleted: HashSet<UniqueId> = stack
+            .iter()
+            .filter_map(|msg| {
+                match msg {
+                    MessageEnum::DeleteOrder(del) => Some(UniqueId::from_delete_order(del)),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        for msg in stack {
+            match msg {
+                MessageEnum::AddOrder(add) => {
+                    let cause = if deleted.contains(&UniqueId::from_add_order(add)) {
+                        BboCause::Modify
+                    } else {
+                        BboCause::Add
+                    };
+                    self.insert_cause(add.order_book_id, cause);
+                }
+                MessageEnum::DeleteOrder(del) => {
+                    self.insert_cause(del.order_book_id, BboCause::Cancel);
+                }
+                MessageEnum::Executed(m) => self.insert_cause(m.order_book_id, BboCause::Trade),
+                MessageEnum::ExecutionWithPriceInfo(m) => {
+                    self.insert_cause(m.order_book_id, BboCause::Trade)
+                }
+                MessageEnum::LegPrice(m) => self.insert_cause(m.order_book_id, BboCause::Trade),
+                _ => (),
+            }
+        }
+    }
+
+    fn order_book_id_with_changes(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        changes: &HashSet<i64>,
+    ) {
+        let mut ids: Vec<i64> = changes.iter().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let Some(book) = order_book_map.get(&id) else {
+                continue;
+            };
+            let bbo = (book.best_bid(), book.best_ask());
+            if self.last.insert(id, bbo).unwrap_or_default() == bbo {
+                continue;
+            }
+            // changes only come from the tags above, the fallback is not expected to be used
+            let cause = self.causes.get(&id).copied().unwrap_or(BboCause::Modify);
+            (self.f)(&BboUpdate {
+                timestamp: *timestamp,
+                order_book_id: id,
+                best_bid: bbo.0,
+                best_ask: bbo.1,
+                cause,
+            });
+        }
+    }
+}
+
+#[test]
+fn test_bbo_tracker() {
+    use crate::{
+        replay,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        // behind the best, no update
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),2,FUT_NK225M_2109(590334),S,2,5,290050000,0,2",
+        "A,2021-03-01T00:00:03.000000000(1614556803000000000),3,FUT_NK225M_2109(590334),B,1,5,289950000,0,2",
+        "E,2021-03-01T00:00:04.000000000(1614556804000000000),1,FUT_NK225M_2109(590334),S,2,100,0,,",
+        "D,2021-03-01T00:00:05.000000000(1614556805000000000),3,FUT_NK225M_2109(590334),B",
+        "A,2021-03-01T00:00:05.000000000(1614556805000000000),3,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "D,2021-03-01T00:00:06.000000000(1614556806000000000),2,FUT_NK225M_2109(590334),S",
+    ];
+
+    let mut updates = vec![];
+    replay(
+        &file,
+        &mut BboTracker::new(|u: &BboUpdate| updates.push(*u)),
+    );
+
+    assert_eq!(
+        updates.iter().map(|u| u.cause).collect::<Vec<_>>(),
+        vec![
+            BboCause::Add,
+            BboCause::Add,
+            BboCause::Trade,
+            BboCause::Modify
+        ]
+    );
+    assert_eq!(
+        updates[2].best_ask,
+        Some(PriceLevelView {
+            price: 290000000,
+            qty: 3
+        })
+    );
+    assert_eq!(updates[3].best_bid.map(|l| l.price), Some(289900000));
+}
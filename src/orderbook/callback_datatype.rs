@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use chrono::NaiveDateTime;
 use serde::{Serialize, Deserialize};
 
 use crate::datatypes::*;
@@ -26,14 +27,25 @@ pub struct CTagWithCorrespondingPTag {
     pub p_tags: Vec<LegPrice>,
 }
 impl CTagWithCorrespondingPTag {
+    /// order book of the first `C` tag.
+    ///
+    /// Panics for prints without `C` tags, which are handed over since `P` tags are kept.
+    #[deprecated(note = "use `try_order_book_id` instead")]
     pub fn order_book_id(&self) -> i64 {
         self.c_tag
-            .iter()
-            .next()
+            .first()
             .unwrap_or_else(|| panic!("{self:#?}"))
             .order_book_id
     }
 
+    /// order book of the first `C` tag, or of the first `P` tag for prints without `C` tags
+    pub fn try_order_book_id(&self) -> Option<i64> {
+        self.c_tag
+            .first()
+            .map(|c| c.order_book_id)
+            .or_else(|| self.p_tags.first().map(|p| p.order_book_id))
+    }
+
     pub fn qty_by_order_book_id(&self) -> HashMap<i64, i64> {
         let mut map = HashMap::new();
         for i in self.c_tag.iter() {
@@ -42,23 +54,105 @@ impl CTagWithCorrespondingPTag {
         map
     }
 
+    /// quantity executed on resting orders. 0 for prints without `C` tags
     pub fn executed_quantity(&self) -> i64 {
         if cfg!(test) {
             self._test();
         }
-        if self.c_tag.len() > 1 {
-            self.c_tag
-                .iter()
-                .filter(|i| i.side == Side::Buy)
-                .fold(0, |a, b| a + b.executed_quantity)
-        } else {
-            self.c_tag[0].executed_quantity
+        match self.c_tag.len() {
+            0 => 0,
+            1 => self.c_tag[0].executed_quantity,
+            _ => {
+                self.c_tag
+                    .iter()
+                    .filter(|i| i.side == Side::Buy)
+                    .fold(0, |a, b| a + b.executed_quantity)
+            }
         }
     }
 
+    /// falls back to the `P` tags for prints without `C` tags, then to false
     pub fn occured_at_cross(&self) -> bool {
-        // this function is not expected to be called when there is no item in `c_tag` field
-        self.c_tag[0].occurred_at_cross
+        self.c_tag
+            .first()
+            .map(|c| c.occurred_at_cross)
+            .or_else(|| self.p_tags.first().map(|p| p.occurred_at_cross))
+            .unwrap_or(false)
+    }
+
+    /// reconstructs trades from `C` and `P` tags.
+    ///
+    /// `C` tags with the same match id on the same order book are merged into a single trade.
+    /// Each `P` tag becomes a trade without passive orders.
+    pub fn trades(&self) -> Vec<Trade> {
+        // (order_book_id, match_id) => c tags, in the order they were received
+        let mut groups: Vec<((i64, i64), Vec<&ExecutionWithPriceInfo>)> = vec![];
+        for c in self.c_tag.iter() {
+            let key = (c.order_book_id, c.match_id);
+            match groups.iter_mut().find(|(k, _)| k == &key) {
+                Some((_, group)) => group.push(c),
+                None => groups.push((key, vec![c])),
+            }
+        }
+
+        let mut trades = Vec::with_capacity(groups.len() + self.p_tags.len());
+        for (_, group) in groups {
+            let first = group[0];
+            let has_both_sides = group.iter().any(|c| c.side != first.side);
+            // when both sides are reported, the quantity is counted on the buy side
+            let quantity = group
+                .iter()
+                .filter(|c| !has_both_sides || c.side == Side::Buy)
+                .fold(0, |a, b| a + b.executed_quantity);
+            let aggressor_side = if first.occurred_at_cross {
+                AggressorSide::Auction
+            } else if has_both_sides {
+                AggressorSide::Unknown
+            } else {
+                AggressorSide::from_passive_side(first.side)
+            };
+            trades.push(Trade {
+                timestamp: first.timestamp,
+                order_book_id: first.order_book_id,
+                price: first.trade_price,
+                quantity,
+                match_id: first.match_id.to_string(),
+                combo_group_id: first.combo_group_id,
+                aggressor_side,
+                passive_orders: group
+                    .iter()
+                    .map(|c| {
+                        UniqueId {
+                            order_book_id: c.order_book_id,
+                            order_id: c.order_id,
+                            side: c.side,
+                        }
+                    })
+                    .collect(),
+                occurred_at_cross: first.occurred_at_cross,
+                source: TradeSource::ExecutionWithPriceInfo,
+            });
+        }
+
+        for p in self.p_tags.iter() {
+            trades.push(Trade {
+                timestamp: p.timestamp,
+                order_book_id: p.order_book_id,
+                price: p.trade_price,
+                quantity: p.quantity,
+                match_id: p.match_id.to_string(),
+                combo_group_id: p.combo_group_id,
+                aggressor_side: if p.occurred_at_cross {
+                    AggressorSide::Auction
+                } else {
+                    AggressorSide::Unknown
+                },
+                passive_orders: vec![],
+                occurred_at_cross: p.occurred_at_cross,
+                source: TradeSource::LegPrice,
+            });
+        }
+        trades
     }
 
     fn _test(&self) {
@@ -106,3 +200,195 @@ pub enum ModifyType {
     /// used when both `ReduceQty` and `PriceChange` is not observed
     Neither
 }
+
+/// message the trade was reconstructed from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TradeSource {
+    /// `E` tag
+    Executed,
+    /// `C` tag
+    ExecutionWithPriceInfo,
+    /// `P` tag
+    LegPrice,
+}
+
+/// side of the order that initiated the trade
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AggressorSide {
+    /// buy initiated
+    Buy,
+    /// sell initiated
+    Sell,
+    /// trade occurred in an auction (板寄せ), there is no aggressor
+    Auction,
+    /// could not be determined
+    Unknown,
+}
+
+impl AggressorSide {
+    /// aggressor is on the other side of the passive order
+    pub fn from_passive_side(side: Side) -> Self {
+        match side {
+            Side::Buy => AggressorSide::Sell,
+            Side::Sell => AggressorSide::Buy,
+        }
+    }
+}
+
+/// A single print on the tape, reconstructed from `E`, `C` or `P` tag.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Trade {
+    pub timestamp: NaiveDateTime,
+    pub order_book_id: i64,
+    pub price: i64,
+    pub quantity: i64,
+    pub match_id: String,
+    pub combo_group_id: i64,
+    /// See `infer_aggressor_sides`
+    pub aggressor_side: AggressorSide,
+    /// resting orders that were executed against. Empty for `P` tag.
+    pub passive_orders: Vec<UniqueId>,
+    /// true if the trade occurred in an auction (板寄せ)
+    pub occurred_at_cross: bool,
+    pub source: TradeSource,
+}
+
+impl Trade {
+    /// `E` tag does not carry a price, the price of the resting order is used.
+    pub fn from_execution(execution: &OrderExecution) -> Self {
+        let msg = &execution.msg;
+        Trade {
+            timestamp: msg.timestamp,
+            order_book_id: msg.order_book_id,
+            price: execution.matched_order_after_execution.price,
+            quantity: msg.executed_quantity,
+            match_id: msg.match_id.clone(),
+            combo_group_id: msg.combo_group_id,
+            aggressor_side: AggressorSide::from_passive_side(msg.side),
+            passive_orders: vec![UniqueId {
+                order_book_id: msg.order_book_id,
+                order_id: msg.order_id,
+                side: msg.side,
+            }],
+            occurred_at_cross: false,
+            source: TradeSource::Executed,
+        }
+    }
+
+    pub fn is_buy_initiated(&self) -> bool {
+        self.aggressor_side == AggressorSide::Buy
+    }
+
+    pub fn is_sell_initiated(&self) -> bool {
+        self.aggressor_side == AggressorSide::Sell
+    }
+
+    /// price * quantity
+    pub fn notional(&self) -> i128 {
+        self.price as i128 * self.quantity as i128
+    }
+}
+
+#[test]
+fn test_trades() {
+    use crate::{
+        replay,
+        OrderBook,
+        OrderBookRunTimeCallback,
+    };
+
+    struct Tape(Vec<Trade>);
+    impl OrderBookRunTimeCallback for Tape {
+        fn trades(
+            &mut self,
+            _order_book_map: &HashMap<i64, OrderBook>,
+            _timestamp: &NaiveDateTime,
+            trades: Vec<Trade>,
+        ) {
+            self.0.extend(trades);
+        }
+    }
+
+    let file = [
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),301531636,PUT_NK225_210305W_29500,136478320,136478320,1,JPY,4,0,0,1,0,0,0,500,29500,20210305,0,2",
+        "A,2021-02-28T21:07:51.000000000(1614546471000000000),10,PUT_NK225_210305W_29500(301531636),S,1,3,3200000,0,2",
+        "A,2021-02-28T21:07:51.000000000(1614546471000000000),11,PUT_NK225_210305W_29500(301531636),B,1,2,3100000,0,2",
+        "A,2021-02-28T21:07:51.000000000(1614546471000000000),12,PUT_NK225_210305W_29500(301531636),S,2,2,3100000,0,2",
+        "E,2021-03-01T00:06:20.042573706(1614557180042573706),10,PUT_NK225_210305W_29500(301531636),S,1,100,0,,",
+        "C,2021-03-01T00:09:42.006417851(1614557382006417851),11,PUT_NK225_210305W_29500(301531636),B,2,200,0,,,3150000,Y,Y",
+        "C,2021-03-01T00:09:42.006417851(1614557382006417851),12,PUT_NK225_210305W_29500(301531636),S,2,200,0,,,3150000,Y,N",
+    ];
+    let mut tape = Tape(vec![]);
+    replay(&file, &mut tape);
+
+    assert_eq!(tape.0.len(), 2);
+    let e = &tape.0[0];
+    assert_eq!(e.source, TradeSource::Executed);
+    assert_eq!((e.price, e.quantity), (3200000, 1));
+    assert_eq!(e.aggressor_side, AggressorSide::Buy);
+    assert_eq!(e.passive_orders[0].order_id, 10);
+
+    let c = &tape.0[1];
+    assert_eq!(c.source, TradeSource::ExecutionWithPriceInfo);
+    assert_eq!((c.price, c.quantity), (3150000, 2));
+    assert!(c.occurred_at_cross);
+    assert_eq!(c.aggressor_side, AggressorSide::Auction);
+    assert_eq!(c.passive_orders.len(), 2);
+}
+
+#[test]
+fn test_leg_prints() {
+    use crate::{
+        replay,
+        OrderBook,
+        OrderBookRunTimeCallback,
+    };
+
+    #[derive(Default)]
+    struct Prints {
+        groups: Vec<CTagWithCorrespondingPTag>,
+        prints: Vec<LegPrice>,
+    }
+    impl OrderBookRunTimeCallback for Prints {
+        fn ctag_execution(
+            &mut self,
+            _order_book_map: &HashMap<i64, OrderBook>,
+            _timestamp: &NaiveDateTime,
+            executed_with_price_info: Vec<CTagWithCorrespondingPTag>,
+        ) {
+            self.groups.extend(executed_with_price_info);
+        }
+
+        fn leg_prints(
+            &mut self,
+            _order_book_map: &HashMap<i64, OrderBook>,
+            _timestamp: &NaiveDateTime,
+            leg_prints: Vec<LegPrice>,
+        ) {
+            self.prints.extend(leg_prints);
+        }
+    }
+
+    // prints without resting orders
+    let file = [
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),301531636,PUT_NK225_210305W_29500,136478320,136478320,1,JPY,4,0,0,1,0,0,0,500,29500,20210305,0,2",
+        "P,2021-03-01T00:09:42.006417851(1614557382006417851),300,7,B,2,PUT_NK225_210305W_29500(301531636),3150000,,,,Y",
+        "P,2021-03-01T00:09:43.006417851(1614557383006417851),301,8,B,3,PUT_NK225_210305W_29500(301531636),3160000,,,,N",
+    ];
+    let mut prints = Prints::default();
+    let map = replay(&file, &mut prints);
+
+    assert_eq!(prints.prints.len(), 2);
+    let group = &prints.groups[0];
+    assert!(group.c_tag.is_empty());
+    assert_eq!(group.try_order_book_id(), Some(301531636));
+    assert!(group.occured_at_cross());
+    assert_eq!(group.executed_quantity(), 0);
+    assert_eq!(group.trades().len(), 1);
+
+    let book = &map[&301531636];
+    let stats = &book.trade_statistics;
+    assert_eq!((stats.volume, stats.trade_count), (5, 2));
+    assert_eq!(stats.last_price, Some(3160000));
+    assert_eq!(stats.last_auction_price, Some(3150000));
+}
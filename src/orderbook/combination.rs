@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    CombinationProduct,
+    OrderBook,
+    PriceLevelView,
+    Side,
+};
+
+/// best prices implied from other books
+///
+/// Combination prices follow the M tags: `sum(leg price * leg_ratio)` of the legs with `leg_side` Buy
+/// minus the same sum of the legs with `leg_side` Sell.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ImpliedQuote {
+    /// book the prices are implied for
+    pub order_book_id: i64,
+    /// combination used to imply the prices
+    pub combination_order_book_id: i64,
+    pub bid: Option<PriceLevelView>,
+    pub ask: Option<PriceLevelView>,
+}
+
+fn opposite(side: Side) -> Side {
+    match side {
+        Side::Buy => Side::Sell,
+        Side::Sell => Side::Buy,
+    }
+}
+
+fn sign(side: Side) -> i64 {
+    match side {
+        Side::Buy => 1,
+        Side::Sell => -1,
+    }
+}
+
+fn best(book: &OrderBook, side: Side) -> Option<PriceLevelView> {
+    match side {
+        Side::Buy => book.best_bid(),
+        Side::Sell => book.best_ask(),
+    }
+}
+
+impl OrderBook {
+    /// true if the book has legs
+    pub fn is_combination(&self) -> bool {
+        !self.combination_product_info.is_empty()
+    }
+
+    /// legs of the combination. Empty for an outright.
+    pub fn legs(&self) -> &[CombinationProduct] {
+        &self.combination_product_info
+    }
+
+    /// order book ids of the combinations having this book as a leg
+    pub fn combination_ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.leg_of_combination_info
+            .iter()
+            .map(|m| m.combination_order_book_id)
+    }
+}
+
+/// Implied prices of the combination from the best prices of its legs.
+///
+/// The implied bid buys the legs with `leg_side` Buy at their bids and sells the others at their asks.
+/// Quantities are in combination units. None if the combination or any leg is unknown.
+pub fn implied_combination_quote(
+    order_book_map: &HashMap<i64, OrderBook>,
+    combination_order_book_id: i64,
+) -> Option<ImpliedQuote> {
+    let combination = order_book_map.get(&combination_order_book_id)?;
+    if !combination.is_combination() {
+        return None;
+    }
+    let legs = combination
+        .legs()
+        .iter()
+        .map(|leg| Some((leg, order_book_map.get(&leg.leg_order_book_id)?)))
+        .collect::<Option<Vec<_>>>()?;
+
+    let implied = |side: Side| -> Option<PriceLevelView> {
+        let mut price = 0;
+        let mut qty = i64::MAX;
+        for (leg, book) in legs.iter() {
+            // direction the leg is traded by an order of `side` on the combination
+            let direction = if side.is_buy() {
+                leg.leg_side
+            } else {
+                opposite(leg.leg_side)
+            };
+            let level = best(book, direction)?;
+            price += sign(leg.leg_side) * leg.leg_ratio * level.price;
+            qty = qty.min(level.qty / leg.leg_ratio);
+        }
+        (qty > 0).then_some(PriceLevelView { price, qty })
+    };
+
+    Some(ImpliedQuote {
+        order_book_id: combination_order_book_id,
+        combination_order_book_id,
+        bid: implied(Side::Buy),
+        ask: implied(Side::Sell),
+    })
+}
+
+/// Implied prices of an outright, one per combination having it as a leg, from the best prices of the combination
+/// and of the other legs.
+///
+/// e.g. the best bid of a spread buying this book and selling another one, combined with the best bid of the other
+/// book, implies a bid for this book.
+/// Quantities are in units of this book. A price is None when it is not a multiple of the leg ratio.
+pub fn implied_outright_quotes(
+    order_book_map: &HashMap<i64, OrderBook>,
+    leg_order_book_id: i64,
+) -> Vec<ImpliedQuote> {
+    let Some(book) = order_book_map.get(&leg_order_book_id) else {
+        return vec![];
+    };
+    book.combination_ids()
+        .filter_map(|id| implied_outright_quote(order_book_map, leg_order_book_id, id))
+        .collect()
+}
+
+fn implied_outright_quote(
+    order_book_map: &HashMap<i64, OrderBook>,
+    leg_order_book_id: i64,
+    combination_order_book_id: i64,
+) -> Option<ImpliedQuote> {
+    let combination = order_book_map.get(&combination_order_book_id)?;
+    let target = combination
+        .legs()
+        .iter()
+        .find(|leg| leg.leg_order_book_id == leg_order_book_id)?;
+    let others = combination
+        .legs()
+        .iter()
+        .filter(|leg| leg.leg_order_book_id != leg_order_book_id)
+        .map(|leg| Some((leg, order_book_map.get(&leg.leg_order_book_id)?)))
+        .collect::<Option<Vec<_>>>()?;
+
+    let implied = |side: Side| -> Option<PriceLevelView> {
+        // side of the resting combination order trading the target leg in `side`
+        let combination_side = if target.leg_side == side {
+            Side::Buy
+        } else {
+            Side::Sell
+        };
+        let resting = best(combination, combination_side)?;
+        let mut price = resting.price;
+        let mut units = resting.qty;
+        for (leg, book) in others.iter() {
+            let direction = if combination_side.is_buy() {
+                leg.leg_side
+            } else {
+                opposite(leg.leg_side)
+            };
+            // the other leg is traded against resting orders of the opposite direction
+            let level = best(book, opposite(direction))?;
+            price -= sign(leg.leg_side) * leg.leg_ratio * level.price;
+            units = units.min(level.qty / leg.leg_ratio);
+        }
+        let divisor = sign(target.leg_side) * target.leg_ratio;
+        if units <= 0 || price % divisor != 0 {
+            return None;
+        }
+        Some(PriceLevelView {
+            price: price / divisor,
+            qty: units * target.leg_ratio,
+        })
+    };
+
+    Some(ImpliedQuote {
+        order_book_id: leg_order_book_id,
+        combination_order_book_id,
+        bid: implied(Side::Buy),
+        ask: implied(Side::Sell),
+    })
+}
+
+#[test]
+fn test_implied_quotes() {
+    use crate::{
+        replay,
+        NoOp,
+    };
+
+    // spread: sell 2106, buy 2109
+    let file = [
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),1,FUT_NK225_2106,166090019,166090019,3,JPY,4,0,0,1,0,0,0,510,0,20210611,0,0",
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),2,FUT_NK225_2109,166090019,166090019,3,JPY,4,0,0,1,0,0,0,510,0,20210910,0,0",
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),3,FUT_NK225_2106/2109,166090019,166090019,11,JPY,4,0,0,1,0,0,2,510,0,20210611,0,0",
+        "M,2021-02-28T21:07:50.931282000(1614546470931282000),3,1,S,1",
+        "M,2021-02-28T21:07:50.931282000(1614546470931282000),3,2,B,1",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225_2106(1),B,1,3,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225_2106(1),S,1,2,290000000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),3,FUT_NK225_2109(2),B,1,5,289500000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),4,FUT_NK225_2109(2),S,1,1,289700000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),5,FUT_NK225_2106/2109(3),B,1,4,-450000,0,2",
+        // M tags sent again
+        "M,2021-03-01T00:00:02.000000000(1614556802000000000),3,1,S,1",
+        "M,2021-03-01T00:00:02.000000000(1614556802000000000),3,2,B,1",
+    ];
+    let map = replay(&file, &mut NoOp);
+
+    assert!(map[&3].is_combination());
+    assert_eq!(map[&3].legs().len(), 2);
+    assert_eq!(map[&1].combination_ids().collect::<Vec<_>>(), vec![3]);
+
+    let spread = implied_combination_quote(&map, 3).unwrap();
+    // buy 2109 at its bid, sell 2106 at its ask
+    assert_eq!(
+        spread.bid,
+        Some(PriceLevelView {
+            price: 289500000 - 290000000,
+            qty: 2
+        })
+    );
+    assert_eq!(
+        spread.ask,
+        Some(PriceLevelView {
+            price: 289700000 - 289900000,
+            qty: 1
+        })
+    );
+
+    // the spread bid sells 2106 and buys 2109 from its ask
+    let outright = implied_outright_quotes(&map, 1);
+    assert_eq!(outright.len(), 1);
+    assert_eq!(
+        outright[0].ask,
+        Some(PriceLevelView {
+            price: 289700000 + 450000,
+            qty: 1
+        })
+    );
+    assert_eq!(outright[0].bid, None);
+    // the spread bid buys 2109 and sells 2106 to its bid
+    assert_eq!(
+        implied_outright_quotes(&map, 2)[0].bid,
+        Some(PriceLevelView {
+            price: 289900000 - 450000,
+            qty: 3
+        })
+    );
+}
@@ -0,0 +1,392 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+
+use chrono::NaiveDateTime;
+
+use crate::callback_datatype::*;
+use crate::datatypes::*;
+use crate::{
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+};
+
+/// callbacks run in one pass of the runtime, in order
+pub(crate) trait Children {
+    fn for_each_child(&mut self, f: impl FnMut(&mut dyn OrderBookRunTimeCallback));
+
+    /// called once the children handled `event_end` or `all_done`
+    fn after_event(&mut self) {}
+}
+
+/// Forwards every callback to the children of a type implementing `Children`.
+/// Owned arguments are cloned for each child.
+/// `stop` is asked to every child and is true if any of them wants to stop.
+macro_rules! impl_composite_callback {
+    (impl<$($generic:ident),*> for $ty:ty) => {
+        const _: () = {
+            use std::collections::{
+                HashMap,
+                HashSet,
+            };
+
+            use chrono::NaiveDateTime;
+
+            use $crate::orderbook::Children;
+            use $crate::{
+                CTagWithCorrespondingPTag,
+                Created,
+                LegPrice,
+                MessageEnum,
+                ModifiedOrder,
+                OrderBook,
+                OrderBookRunTimeCallback,
+                OrderDeletion,
+                OrderExecution,
+                SecondTag,
+                Trade,
+            };
+
+            impl<$($generic: OrderBookRunTimeCallback),*> OrderBookRunTimeCallback for $ty {
+                fn stop(&mut self) -> bool {
+                    let mut stop = false;
+                    self.for_each_child(|c| stop |= c.stop());
+                    stop
+                }
+
+                fn event_start(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    stack: &[MessageEnum],
+                ) {
+                    self.for_each_child(|c| c.event_start(order_book_map, timestamp, stack));
+                }
+
+                fn event_end(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    stack: &[MessageEnum],
+                ) {
+                    self.for_each_child(|c| c.event_end(order_book_map, timestamp, stack));
+                    self.after_event();
+                }
+
+                fn second_message(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    second_messages: &[SecondTag],
+                ) {
+                    self.for_each_child(|c| c.second_message(order_book_map, timestamp, second_messages));
+                }
+
+                fn created(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    created: Created,
+                ) {
+                    self.for_each_child(|c| c.created(order_book_map, timestamp, created.clone()));
+                }
+
+                fn order_book_id_with_changes(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    changes: &HashSet<i64>,
+                ) {
+                    self.for_each_child(|c| c.order_book_id_with_changes(order_book_map, timestamp, changes));
+                }
+
+                fn executions(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    executions: Vec<OrderExecution>,
+                ) {
+                    self.for_each_child(|c| c.executions(order_book_map, timestamp, executions.clone()));
+                }
+
+                fn ctag_execution(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    executed_with_price_info: Vec<CTagWithCorrespondingPTag>,
+                ) {
+                    self.for_each_child(|c| {
+                        c.ctag_execution(order_book_map, timestamp, executed_with_price_info.clone())
+                    });
+                }
+
+                fn leg_prints(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    leg_prints: Vec<LegPrice>,
+                ) {
+                    self.for_each_child(|c| c.leg_prints(order_book_map, timestamp, leg_prints.clone()));
+                }
+
+                fn trades(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    trades: Vec<Trade>,
+                ) {
+                    self.for_each_child(|c| c.trades(order_book_map, timestamp, trades.clone()));
+                }
+
+                fn deletions(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    deletion: Vec<OrderDeletion>,
+                ) {
+                    self.for_each_child(|c| c.deletions(order_book_map, timestamp, deletion.clone()));
+                }
+
+                fn modified_orders(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: &NaiveDateTime,
+                    modified_orders: Vec<ModifiedOrder>,
+                ) {
+                    self.for_each_child(|c| c.modified_orders(order_book_map, timestamp, modified_orders.clone()));
+                }
+
+                fn all_done(
+                    &mut self,
+                    order_book_map: &HashMap<i64, OrderBook>,
+                    timestamp: Option<NaiveDateTime>,
+                ) {
+                    self.for_each_child(|c| c.all_done(order_book_map, timestamp));
+                    self.after_event();
+                }
+            }
+        };
+    };
+}
+
+pub(crate) use impl_composite_callback;
+
+macro_rules! impl_tuple_callback {
+    ($($generic:ident $index:tt),+) => {
+        impl<$($generic: OrderBookRunTimeCallback),+> Children for ($($generic,)+) {
+            fn for_each_child(&mut self, mut f: impl FnMut(&mut dyn OrderBookRunTimeCallback)) {
+                $(f(&mut self.$index);)+
+            }
+        }
+
+        impl_composite_callback!(impl<$($generic),+> for ($($generic,)+));
+    };
+}
+
+impl_tuple_callback!(A 0, B 1);
+impl_tuple_callback!(A 0, B 1, C 2);
+impl_tuple_callback!(A 0, B 1, C 2, D 3);
+impl_tuple_callback!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple_callback!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_tuple_callback!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_tuple_callback!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// e.g. `Vec<Box<dyn OrderBookRunTimeCallback>>`
+impl<T: OrderBookRunTimeCallback> Children for Vec<T> {
+    fn for_each_child(&mut self, mut f: impl FnMut(&mut dyn OrderBookRunTimeCallback)) {
+        for child in self.iter_mut() {
+            f(child);
+        }
+    }
+}
+
+impl_composite_callback!(impl<T> for Vec<T>);
+
+impl<T: OrderBookRunTimeCallback + ?Sized> OrderBookRunTimeCallback for Box<T> {
+    fn stop(&mut self) -> bool {
+        (**self).stop()
+    }
+
+    fn event_start(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        (**self).event_start(order_book_map, timestamp, stack)
+    }
+
+    fn event_end(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        (**self).event_end(order_book_map, timestamp, stack)
+    }
+
+    fn second_message(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        second_messages: &[SecondTag],
+    ) {
+        (**self).second_message(order_book_map, timestamp, second_messages)
+    }
+
+    fn created(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        created: Created,
+    ) {
+        (**self).created(order_book_map, timestamp, created)
+    }
+
+    fn order_book_id_with_changes(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        changes: &HashSet<i64>,
+    ) {
+        (**self).order_book_id_with_changes(order_book_map, timestamp, changes)
+    }
+
+    fn executions(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        executions: Vec<OrderExecution>,
+    ) {
+        (**self).executions(order_book_map, timestamp, executions)
+    }
+
+    fn ctag_execution(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        executed_with_price_info: Vec<CTagWithCorrespondingPTag>,
+    ) {
+        (**self).ctag_execution(order_book_map, timestamp, executed_with_price_info)
+    }
+
+    fn leg_prints(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        leg_prints: Vec<LegPrice>,
+    ) {
+        (**self).leg_prints(order_book_map, timestamp, leg_prints)
+    }
+
+    fn trades(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        trades: Vec<Trade>,
+    ) {
+        (**self).trades(order_book_map, timestamp, trades)
+    }
+
+    fn deletions(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        deletion: Vec<OrderDeletion>,
+    ) {
+        (**self).deletions(order_book_map, timestamp, deletion)
+    }
+
+    fn modified_orders(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        modified_orders: Vec<ModifiedOrder>,
+    ) {
+        (**self).modified_orders(order_book_map, timestamp, modified_orders)
+    }
+
+    fn all_done(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: Option<NaiveDateTime>,
+    ) {
+        (**self).all_done(order_book_map, timestamp)
+    }
+}
+
+#[test]
+fn test_composite_callback() {
+    use crate::{
+        replay,
+        FUT_NK225M_2109,
+    };
+
+    #[derive(Default)]
+    struct Count {
+        events: usize,
+        created: usize,
+        stop_after: Option<usize>,
+    }
+    impl OrderBookRunTimeCallback for Count {
+        fn stop(&mut self) -> bool {
+            self.stop_after.map(|n| self.events >= n).unwrap_or(false)
+        }
+
+        fn event_end(
+            &mut self,
+            _order_book_map: &HashMap<i64, OrderBook>,
+            _timestamp: &NaiveDateTime,
+            _stack: &[MessageEnum],
+        ) {
+            self.events += 1;
+        }
+
+        fn created(
+            &mut self,
+            _order_book_map: &HashMap<i64, OrderBook>,
+            _timestamp: &NaiveDateTime,
+            created: Created,
+        ) {
+            self.created += created.msgs.len();
+        }
+    }
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),2,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "A,2021-03-01T00:00:03.000000000(1614556803000000000),3,FUT_NK225M_2109(590334),S,1,5,290050000,0,2",
+    ];
+
+    let mut pair = (Count::default(), Count::default());
+    replay(&file, &mut pair);
+    assert_eq!((pair.0.events, pair.0.created), (4, 3));
+    assert_eq!((pair.1.events, pair.1.created), (4, 3));
+
+    // the first child to stop stops everything
+    let mut children = vec![
+        Count::default(),
+        Count {
+            stop_after: Some(2),
+            ..Default::default()
+        },
+    ];
+    replay(&file, &mut children);
+    assert_eq!(children[0].events, 2);
+
+    let mut boxed: Vec<Box<dyn OrderBookRunTimeCallback>> = vec![
+        Box::new(Count::default()),
+        Box::new((
+            Count::default(),
+            Count {
+                stop_after: Some(3),
+                ..Default::default()
+            },
+        )),
+    ];
+    let map = replay(&file, &mut boxed);
+    assert_eq!(map[&590334].orders.len(), 2);
+}
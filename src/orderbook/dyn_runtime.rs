@@ -1,226 +1,519 @@
+use std::collections::hash_map::Entry;
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::time::SystemTime;
 
-macro_rules! callback_many {
-    ($( $cb:path )+) => {
-        $( $cb )+ 
-    };
-}
+use chrono::NaiveDateTime;
 
-struct Variables {
-    message_count: usize,
-    key_count: usize,
-    ts: Option<NaiveDateTime>,
-}
+use crate::callback_datatype::*;
+use crate::datatypes::*;
+use crate::{
+    infer_aggressor_sides,
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    RuntimeStats,
+};
 
+/// new add order, delete order, the order it deleted and the time priority of the deleted order,
+/// of an order modified within a message stack
+type ModifiedParts = (
+    Option<Box<AddOrder>>,
+    Option<Box<DeleteOrder>>,
+    Option<AddOrder>,
+    Option<NaiveDateTime>,
+);
+
+/// buffers cleared and reused for every message stack.
+///
+/// Exceptions: `executions`, `executed_with_price_info`, `leg_prints`, `trades`, `deletion`,
+/// `created` and `modified_orders` are handed over to the callbacks by value. They are taken out
+/// when handed over, so a stack using them allocates them again.
 #[derive(Default)]
 struct PerTimeframe {
     changes: HashSet<i64>,
+    add_set: HashSet<UniqueId>,
+    del_set: HashSet<UniqueId>,
+    modified_order_id_map: HashMap<UniqueId, ModifiedParts>,
+    second_messages: Vec<SecondTag>,
+    /// put order retrieved after `Executed` message is handled
     executions: Vec<OrderExecution>,
-    ctag_with_ptag: Vec<CTagWithCorrespondingPTag>,
+    /// put order retrieved after `ExecutionWithPriceInfo` message is handled
+    executed_with_price_info: Vec<CTagWithCorrespondingPTag>,
+    /// put order retrieved after `DeleteOrder` message is handled
     deletion: Vec<OrderDeletion>,
-    modified_order_id_map: HashMap<UniqueId, (Option<Box<AddOrder>>, Option<Box<DeleteOrder>>, Option<AddOrder>)>,
-    second_messages: Vec<SecondTag>
+    /// `LegPrice` messages
+    leg_prints: Vec<LegPrice>,
+    /// newly created orders
+    created: Vec<AddOrder>,
+    /// reconstructed from `executions` and `executed_with_price_info`
+    trades: Vec<Trade>,
+    modified_orders: Vec<ModifiedOrder>,
+}
+
+impl PerTimeframe {
+    fn clear(&mut self) {
+        self.changes.clear();
+        self.add_set.clear();
+        self.del_set.clear();
+        self.modified_order_id_map.clear();
+        self.second_messages.clear();
+        self.executions.clear();
+        self.executed_with_price_info.clear();
+        self.deletion.clear();
+        self.leg_prints.clear();
+        self.created.clear();
+        self.trades.clear();
+        self.modified_orders.clear();
+    }
+}
+
+/// Stateful runtime processing one message stack at a time.
+///
+/// `order_book_runtime` drives it with an iterator. Drive it yourself with `process_group` and
+/// call `all_done` at the end.
+pub struct Runtime {
+    pub order_book_map: HashMap<i64, OrderBook>,
+    message_count: usize,
+    key_count: usize,
+    /// timestamp of the last processed message stack
+    ts: Option<NaiveDateTime>,
+    started_at: SystemTime,
+    tf: PerTimeframe,
 }
 
-fn process_msg(
-    order_book_map: &mut HashMap<i64, OrderBook>,
-    timestamp: NaiveDateTime,
-    stack: Vec<MessageEnum>,
-    var: &mut Variables,
-    tf: &mut PerTimeframe
-) {
-    
-    var.message_count += stack.len();
-    var.key_count += 1;
-
-    var.ts.replace(timestamp);
-    tf.changes.clear();
-    // sort stack
-    // stacks put order retrieved after `Executed` message  is handled
-    let mut executions = &mut tf.executions;
-    // stacks put order retrieved after `ExecutionWithPriceInfo` message is handled
-    let mut executed_with_price_info = &mut tf.ctag_with_ptag;
-    // stacks put order retrieved after `DeleteOrder` message is handled
-    let mut deletion = &mut tf.deletion;
-
-    let mut modified_order_id_map = {
-        let mut add_set = HashSet::new();
-        let mut del_set = HashSet::new();
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new(HashMap::new())
+    }
+}
+
+impl Runtime {
+    /// starts from the order books in `order_book_map`
+    pub fn new(order_book_map: HashMap<i64, OrderBook>) -> Self {
+        Self {
+            order_book_map,
+            message_count: 0,
+            key_count: 0,
+            ts: None,
+            started_at: SystemTime::now(),
+            tf: PerTimeframe::default(),
+        }
+    }
+
+    /// timestamp of the last processed message stack
+    pub fn last_timestamp(&self) -> Option<NaiveDateTime> {
+        self.ts
+    }
+
+    /// `time_taken` is the time since the runtime was created
+    pub fn stats(&self) -> RuntimeStats {
+        RuntimeStats {
+            message_count: self.message_count,
+            key_count: self.key_count,
+            time_taken: self.started_at.elapsed().unwrap_or_default(),
+        }
+    }
+
+    pub fn into_order_book_map(self) -> HashMap<i64, OrderBook> {
+        self.order_book_map
+    }
+
+    /// calls `OrderBookRunTimeCallback::all_done`
+    pub fn all_done<A>(&mut self, callback: &mut A)
+    where
+        A: OrderBookRunTimeCallback,
+    {
+        callback.all_done(&self.order_book_map, self.ts);
+    }
+
+    /// Processes the messages sharing `timestamp` and calls the callbacks.
+    ///
+    /// Returns false when `callback.stop()` returned true. The stack may be partially applied
+    /// to the order books in that case, and no more stack should be processed.
+    ///
+    /// A `R` tag for a known order book replaces its product info and keeps its orders.
+    /// Other tags of unknown order books are skipped, as the `R` tag may have been filtered out.
+    pub fn process_group<A>(
+        &mut self,
+        timestamp: NaiveDateTime,
+        stack: Vec<MessageEnum>,
+        callback: &mut A,
+    ) -> bool
+    where
+        A: OrderBookRunTimeCallback,
+    {
+        let order_book_map = &mut self.order_book_map;
+        let tf = &mut self.tf;
+        tf.clear();
+        if callback.stop() {
+            return false;
+        }
+
+        self.message_count += stack.len();
+        self.key_count += 1;
+
+        self.ts.replace(timestamp);
+        callback.event_start(order_book_map, &timestamp, &stack[..]);
+        // pre processing
 
         for i in stack.iter() {
             match i {
                 MessageEnum::AddOrder(add) => {
-                    let id = UniqueId::from_add_order(add);
-                    add_set.insert(id);
+                    tf.add_set.insert(UniqueId::from_add_order(add));
                 }
                 MessageEnum::DeleteOrder(del) => {
-                    let id = UniqueId::from_delete_order(del);
-                    del_set.insert(id);
+                    tf.del_set.insert(UniqueId::from_delete_order(del));
                 }
                 _ => (),
             }
         }
-
-        let mut modified_orders_map = HashMap::new();
-        for id in add_set.intersection(&del_set) {
-            modified_orders_map.insert(*id, (None, None, None));
+        for id in tf.add_set.intersection(&tf.del_set) {
+            tf.modified_order_id_map
+                .insert(*id, (None, None, None, None));
         }
-        modified_orders_map
-    };
-
-    let mut second_messages = vec![];
+        let modified_order_id_map = &mut tf.modified_order_id_map;
 
-    for msg in stack.clone() {
-        match msg {
-            MessageEnum::SecondTag(msg) => {
-                second_messages.push(*msg);
+        for msg in stack.iter() {
+            if callback.stop() {
+                return false;
             }
-            // this one creates order book
-            MessageEnum::ProductInfo(info) => {
-                let order_book_id = info.order_book_id;
-                let check = order_book_map.insert(order_book_id, OrderBook::new(*info));
-                match check {
-                    Some(ob) => {
-                        // check if the product_info is pointing at the same instrument
-                        let mut i1 = ob.product_info.clone();
-                        let mut i2 = order_book_map
-                            .get(&ob.order_book_id())
-                            .unwrap()
-                            .product_info
-                            .clone();
-                        i1.timestamp = NaiveDateTime::MAX;
-                        i2.timestamp = NaiveDateTime::MAX;
-                        // put it back if it is the same.
-                        if i1 == i2 {
-                            order_book_map.insert(order_book_id, ob);
-                        } else {
-                            unimplemented!("{:#?}", (&i1, &i2));
-                        };
+
+            match msg {
+                MessageEnum::SecondTag(msg) => {
+                    tf.second_messages.push(msg.as_ref().clone());
+                }
+                // this one creates order book
+                MessageEnum::ProductInfo(info) => {
+                    match order_book_map.entry(info.order_book_id) {
+                        Entry::Occupied(mut entry) => {
+                            let book = entry.get_mut();
+                            // ignore the timestamp when the same R tag is sent again
+                            let mut known = book.product_info.clone();
+                            known.timestamp = info.timestamp;
+                            if known != **info {
+                                book.product_info = info.as_ref().clone();
+                            }
+                        }
+                        Entry::Vacant(entry) => {
+                            entry.insert(OrderBook::new(info.as_ref().clone()));
+                        }
                     }
-                    None => (), // ok!
                 }
-            }
-            // order book meta data update
-            MessageEnum::TradingStatusInfo(msg) => {
-                let book = order_book_map.get_mut(&msg.order_book_id);
-
-                if let Some(book) = book {
-                    book.push_trading_status(*msg);
-                } else {
-                    println!("{}", err_msg(msg.order_book_id, &msg));
-                };
-            }
-            MessageEnum::TickSize(msg) => {
-                order_book_map
-                    .get_mut(&msg.order_book_id)
-                    .expect(&err_msg(msg.order_book_id, &msg))
-                    .append_l(*msg);
-            }
-            MessageEnum::EquilibriumPrice(msg) => {
-                let check = order_book_map.get_mut(&msg.order_book_id);
-
-                if let Some(book) = check {
-                    book.push_last_equilibrium_price(*msg);
-                } else {
-                    eprintln!("{}", err_msg(msg.order_book_id, &msg));
+                // order book meta data update. The R tag may have been filtered out
+                MessageEnum::TradingStatusInfo(msg) => {
+                    if let Some(book) = order_book_map.get_mut(&msg.order_book_id) {
+                        book.push_trading_status(msg.as_ref().clone());
+                    }
                 }
-            }
-            // order CRUD. New order insertion, deletion, execution (reduction of order qty)
-            MessageEnum::AddOrder(msg) => {
-                tf.changes.insert(msg.order_book_id);
-                let id = (&*msg).try_into().unwrap();
-                if modified_order_id_map.contains_key(&id) {
-                    modified_order_id_map.entry(id).and_modify(|opts| {
+                MessageEnum::TickSize(msg) => {
+                    if let Some(book) = order_book_map.get_mut(&msg.order_book_id) {
+                        book.append_l(msg.as_ref().clone());
+                    }
+                }
+                MessageEnum::EquilibriumPrice(msg) => {
+                    if let Some(book) = order_book_map.get_mut(&msg.order_book_id) {
+                        book.push_last_equilibrium_price(msg.as_ref().clone());
+                    }
+                }
+                // order CRUD. New order insertion, deletion, execution (reduction of order qty).
+                // Skipped for unknown order books, the R tag may have been filtered out
+                MessageEnum::AddOrder(msg) => {
+                    let id = msg.as_ref().try_into().unwrap();
+                    let Some(book) = order_book_map.get_mut(&msg.order_book_id) else {
+                        modified_order_id_map.remove(&id);
+                        continue;
+                    };
+                    book.add(**msg);
+                    tf.changes.insert(msg.order_book_id);
+                    if let Some(opts) = modified_order_id_map.get_mut(&id) {
+                        // a quantity reduction keeps the time priority of the deleted order
+                        if let (Some(previous), Some(priority)) = (&opts.2, opts.3) {
+                            if previous.price == msg.price && previous.quantity != msg.quantity {
+                                book.keep_priority(msg.order_id, msg.side, priority);
+                            }
+                        }
                         opts.0.replace(msg.clone());
-                    });
+                    } else {
+                        tf.created.push(**msg);
+                    };
                 }
-                order_book_map
-                    .get_mut(&msg.order_book_id)
-                    .expect(&err_msg(msg.order_book_id, &msg))
-                    .add(*msg);
-            }
-            MessageEnum::DeleteOrder(msg) => {
-                tf.changes.insert(msg.order_book_id);
-                // original add order
-                let add_order = order_book_map
-                    .get_mut(&msg.order_book_id)
-                    .expect(&err_msg(msg.order_book_id, &msg))
-                    .delete(&msg);
-
-                // modify
-                let id = (&*msg).try_into().unwrap();
-                if modified_order_id_map.contains_key(&id) {
-                    modified_order_id_map.entry(id).and_modify(|opts| {
-                        opts.1.replace(msg);
+                MessageEnum::DeleteOrder(msg) => {
+                    let id = msg.as_ref().try_into().unwrap();
+                    let Some(book) = order_book_map.get_mut(&msg.order_book_id) else {
+                        modified_order_id_map.remove(&id);
+                        continue;
+                    };
+                    let priority = book.priority_timestamp(msg.order_id, msg.side);
+                    // original add order
+                    let add_order = book.delete(msg);
+                    tf.changes.insert(msg.order_book_id);
+
+                    // modify
+                    if let Some(opts) = modified_order_id_map.get_mut(&id) {
+                        opts.1.replace(msg.clone());
                         opts.2.replace(add_order);
-                    });
-                } else {
-                    // deletion
-                    let item = OrderDeletion {
-                        deleted_order: add_order,
-                        msg: *msg,
+                        opts.3 = priority;
+                    } else {
+                        // deletion
+                        let item = OrderDeletion {
+                            deleted_order: add_order,
+                            msg: msg.as_ref().clone(),
+                        };
+                        tf.deletion.push(item);
+                    }
+                }
+                MessageEnum::Executed(msg) => {
+                    let Some(book) = order_book_map.get_mut(&msg.order_book_id) else {
+                        continue;
                     };
-                    deletion.push(item);
+                    let add_order = book.executed(msg);
+                    tf.changes.insert(msg.order_book_id);
+                    let item = OrderExecution {
+                        matched_order_after_execution: add_order,
+                        msg: msg.as_ref().clone(),
+                    };
+                    tf.executions.push(item);
                 }
-            }
-            MessageEnum::Executed(msg) => {
-                tf.changes.insert(msg.order_book_id);
-                let add_order = order_book_map
-                    .get_mut(&msg.order_book_id)
-                    .expect(&err_msg(msg.order_book_id, &msg))
-                    .executed(&msg);
-                let item = OrderExecution {
-                    matched_order_after_execution: add_order,
-                    msg: *msg,
-                };
-                executions.push(item);
-            }
-            MessageEnum::ExecutionWithPriceInfo(msg) => {
-                tf.changes.insert(msg.order_book_id);
-                let add_order = order_book_map
-                    .get_mut(&msg.order_book_id)
-                    .expect(&err_msg(msg.order_book_id, &msg))
-                    .c_executed(&msg);
-
-                'a: {
-                    for i in executed_with_price_info.iter_mut() {
-                        if i.c_tag.match_id == msg.match_id {
-                            i.paired_ctag.replace(*msg);
-                            i.matched_add_order2.replace(add_order);
+                MessageEnum::ExecutionWithPriceInfo(msg) => {
+                    let Some(book) = order_book_map.get_mut(&msg.order_book_id) else {
+                        continue;
+                    };
+                    let add_order = book.c_executed(msg);
+                    tf.changes.insert(msg.order_book_id);
+
+                    'a: {
+                        for i in tf.executed_with_price_info.iter_mut() {
+                            if i.combo_group_id == msg.combo_group_id {
+                                i.c_tag.push(msg.as_ref().clone());
+                                i.matched_add_order.push(add_order);
+                                break 'a;
+                            }
+                        }
+                        tf.executed_with_price_info.push(CTagWithCorrespondingPTag {
+                            combo_group_id: msg.combo_group_id,
+                            c_tag: vec![msg.as_ref().clone()],
+                            matched_add_order: vec![add_order],
+                            p_tags: Vec::with_capacity(2),
+                        });
+                    };
+                }
+                // links the combination book and the leg book. Either may be unknown when the R tag was filtered out
+                MessageEnum::CombinationProduct(msg) => {
+                    if let Some(book) = order_book_map.get_mut(&msg.leg_order_book_id) {
+                        book.push_leg_of_combination(msg.as_ref().clone())
+                    };
+                    if let Some(book) = order_book_map.get_mut(&msg.combination_order_book_id) {
+                        book.push_combination_orderbook(msg.as_ref().clone())
+                    };
+                }
+                MessageEnum::LegPrice(msg) => 'a: {
+                    tf.leg_prints.push(msg.as_ref().clone());
+                    for i in tf.executed_with_price_info.iter_mut() {
+                        if msg.combo_group_id == i.combo_group_id {
+                            i.p_tags.push(msg.as_ref().clone());
                             break 'a;
                         }
                     }
-                    executed_with_price_info.push(CTagWithCorrespondingPTag {
-                        c_tag: *msg,
-                        paired_ctag: None,
-                        p_tags: Vec::with_capacity(2),
-                        matched_add_order: add_order,
-                        matched_add_order2: None,
+                    tf.executed_with_price_info.push(CTagWithCorrespondingPTag {
+                        combo_group_id: msg.combo_group_id,
+                        c_tag: vec![],
+                        matched_add_order: vec![],
+                        p_tags: vec![msg.as_ref().clone()],
                     });
-                };
-            }
-            // things that I don't know what to do with
-            MessageEnum::CombinationProduct(msg) => {
-                if let Some(book) = order_book_map.get_mut(&msg.combination_order_book_id) {
-                    book.push_combination_orderbook(*msg)
-                } else {
-                    unreachable!("{} => {:?}", msg.combination_order_book_id, msg);
-                };
-            }
-            MessageEnum::LegPrice(msg) => 'a: {
-                for i in executed_with_price_info.iter_mut() {
-                    if msg.combo_group_id == i.c_tag.combo_group_id {
-                        i.p_tags.push(*msg);
-                        break 'a;
-                    }
                 }
-                unreachable!("CTagWithCorrespondingPTag not found for LegPrice {msg:#?}\n");
+                MessageEnum::SystemEventInfo(_msg) => {
+                    //
+                }
+            };
+        }
+
+        // statistics are up to date in every callback of this stack
+        tf.trades.extend(
+            tf.executions
+                .iter()
+                .map(Trade::from_execution)
+                .chain(tf.executed_with_price_info.iter().flat_map(|i| i.trades())),
+        );
+        infer_aggressor_sides(&mut tf.trades, &stack);
+        for trade in tf.trades.iter() {
+            if let Some(book) = order_book_map.get_mut(&trade.order_book_id) {
+                book.record_trade(trade);
             }
-            MessageEnum::SystemEventInfo(_msg) => {
-                //
+        }
+
+        if !tf.second_messages.is_empty() {
+            callback.second_message(order_book_map, &timestamp, &tf.second_messages)
+        }
+
+        if !tf.created.is_empty() {
+            callback.created(
+                order_book_map,
+                &timestamp,
+                Created {
+                    msgs: std::mem::take(&mut tf.created),
+                    is_fas: !(tf.executions.is_empty() && tf.executed_with_price_info.is_empty()),
+                    executed_qty: tf
+                        .executions
+                        .iter()
+                        .fold(0, |a, b| a + b.msg.executed_quantity)
+                        + tf.executed_with_price_info
+                            .iter()
+                            .fold(0, |a, b| a + b.executed_quantity()),
+                },
+            );
+        }
+
+        if !tf.executions.is_empty() {
+            callback.executions(
+                order_book_map,
+                &timestamp,
+                std::mem::take(&mut tf.executions),
+            );
+        }
+
+        if !tf.executed_with_price_info.is_empty() {
+            callback.ctag_execution(
+                order_book_map,
+                &timestamp,
+                std::mem::take(&mut tf.executed_with_price_info),
+            );
+        }
+
+        if !tf.leg_prints.is_empty() {
+            callback.leg_prints(
+                order_book_map,
+                &timestamp,
+                std::mem::take(&mut tf.leg_prints),
+            );
+        }
+
+        if !tf.trades.is_empty() {
+            callback.trades(order_book_map, &timestamp, std::mem::take(&mut tf.trades));
+        }
+
+        if !tf.deletion.is_empty() {
+            callback.deletions(order_book_map, &timestamp, std::mem::take(&mut tf.deletion));
+        }
+
+        if !modified_order_id_map.is_empty() {
+            for (id, tup) in modified_order_id_map.drain() {
+                match tup {
+                    (Some(modify_msg), Some(delete_msg), Some(previous_add_order), _) => {
+                        // [減数訂正が可能であること](https://faq.sbineotrade.jp/answer/608752eba86ee343fd1372fc)
+                        let modify_type = if modify_msg.quantity == previous_add_order.quantity
+                            && modify_msg.price == previous_add_order.price
+                        {
+                            ModifyType::Neither
+                        } else if modify_msg.price == previous_add_order.price {
+                            ModifyType::ReduceQty
+                        } else if modify_msg.quantity == previous_add_order.quantity {
+                            ModifyType::PriceChange
+                        } else {
+                            ModifyType::Both
+                        };
+
+                        let ord = ModifiedOrder {
+                            id,
+                            modify_msg: *modify_msg,
+                            delete_msg: *delete_msg,
+                            previous_add_order,
+                            modify_type,
+                        };
+                        tf.modified_orders.push(ord);
+                    }
+                    _ => unreachable!(),
+                };
             }
-        };
+            callback.modified_orders(
+                order_book_map,
+                &timestamp,
+                std::mem::take(&mut tf.modified_orders),
+            );
+        }
+
+        if !tf.changes.is_empty() {
+            callback.order_book_id_with_changes(order_book_map, &timestamp, &tf.changes);
+        }
+
+        // post processing
+        callback.event_end(order_book_map, &timestamp, &stack[..]);
+        true
     }
+}
 
+#[test]
+fn test_runtime_step_by_step() {
+    use crate::{
+        parse,
+        replay,
+        FUT_NK225M_2109,
+    };
 
-    std::mem::take(&mut tf);
+    struct Count(usize);
+    impl OrderBookRunTimeCallback for Count {
+        fn event_end(
+            &mut self,
+            _order_book_map: &HashMap<i64, OrderBook>,
+            _timestamp: &NaiveDateTime,
+            _stack: &[MessageEnum],
+        ) {
+            self.0 += 1;
+        }
+    }
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),2,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "D,2021-03-01T00:00:03.000000000(1614556803000000000),1,FUT_NK225M_2109(590334),B",
+        "A,2021-03-01T00:00:03.000000000(1614556803000000000),1,FUT_NK225M_2109(590334),B,1,3,289900000,0,2",
+    ];
+
+    let mut runtime = Runtime::default();
+    let mut count = Count(0);
+    for (timestamp, stack) in parse(&file) {
+        assert!(runtime.process_group(timestamp, stack, &mut count));
+        assert_eq!(runtime.last_timestamp(), Some(timestamp));
+    }
+    runtime.all_done(&mut count);
+    assert_eq!(count.0, 4);
+    assert_eq!(
+        (runtime.stats().key_count, runtime.stats().message_count),
+        (4, 5)
+    );
+
+    // same result as order_book_runtime
+    let map = replay(&file, &mut Count(0));
+    let book = &runtime.order_book_map[&590334];
+    assert_eq!(book.best_bid(), map[&590334].best_bid());
+    assert_eq!(book.best_bid().map(|l| l.qty), Some(3));
+
+    // changed R tag and tags of unknown order books
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "R,2021-03-01T00:00:02.000000000(1614556802000000000),590334,FUT_NK225M_2109,166090019,166090019,3,JPY,4,0,0,1,0,0,0,520,0,20210910,0,0",
+        "L,2021-03-01T00:00:02.000000000(1614556802000000000),PUT_NK225_231208_14500(144835060),10000,10000,999999",
+        "Z,2021-03-01T00:00:02.000000000(1614556802000000000),PUT_NK225_210312_29375(188809716),0,0,-2147483648,3850000,3950000,3,2",
+        // orders of a book whose R tag was filtered out
+        "A,2021-03-01T00:00:03.000000000(1614556803000000000),7,PUT_NK225_231208_14500(144835060),B,1,5,10000,0,2",
+        "D,2021-03-01T00:00:04.000000000(1614556804000000000),7,PUT_NK225_231208_14500(144835060),B",
+        "A,2021-03-01T00:00:04.000000000(1614556804000000000),7,PUT_NK225_231208_14500(144835060),B,1,4,10000,0,2",
+        "E,2021-03-01T00:00:05.000000000(1614556805000000000),7,PUT_NK225_231208_14500(144835060),B,1,103,0,,",
+        "C,2021-03-01T00:00:05.000000000(1614556805000000000),7,PUT_NK225_231208_14500(144835060),B,1,104,0,,,10000,N,N",
+        "D,2021-03-01T00:00:06.000000000(1614556806000000000),7,PUT_NK225_231208_14500(144835060),B",
+    ];
+    let stacks = parse(&file);
+    let changed_at = stacks[2].0;
+    let mut runtime = Runtime::default();
+    for (timestamp, stack) in stacks {
+        assert!(runtime.process_group(timestamp, stack, &mut Count(0)));
+    }
+    assert_eq!(runtime.order_book_map.len(), 1);
+    let book = &runtime.order_book_map[&590334];
+    assert_eq!(book.orders.len(), 1);
+    assert_eq!(book.product_info.timestamp, changed_at);
 }
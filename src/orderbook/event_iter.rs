@@ -0,0 +1,519 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+    VecDeque,
+};
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+
+use crate::callback_datatype::*;
+use crate::datatypes::*;
+use crate::{
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    PriceLevelView,
+    Runtime,
+    TradeStatistics,
+};
+
+/// Top of book and statistics of an order book, taken after a message stack was applied.
+///
+/// Full books are not copied. While the events of a message stack are consumed,
+/// `BookEventIter::order_book_map` is the state after that stack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookView {
+    pub order_book_id: i64,
+    pub symbol: String,
+    pub best_bid: Option<PriceLevelView>,
+    pub best_ask: Option<PriceLevelView>,
+    /// number of resting orders
+    pub order_count: usize,
+    /// last `O` tag
+    pub trading_status: Option<TradingStatusInfo>,
+    /// last `Z` tag
+    pub equilibrium_price: Option<EquilibriumPrice>,
+    pub trade_statistics: TradeStatistics,
+}
+
+impl BookView {
+    pub fn new(book: &OrderBook) -> Self {
+        Self {
+            order_book_id: book.order_book_id(),
+            symbol: book.product_info.symbol.clone(),
+            best_bid: book.best_bid(),
+            best_ask: book.best_ask(),
+            order_count: book.orders.len(),
+            trading_status: book.trading_status.last().cloned(),
+            equilibrium_price: book.equibrium_price.last().cloned(),
+            trade_statistics: book.trade_statistics.clone(),
+        }
+    }
+}
+
+/// views of the books affected by an event. Events of the same message stack share the views.
+pub type BookViews = HashMap<i64, Arc<BookView>>;
+
+/// Events yielded by `BookEventIter`. They carry the same information as the callbacks of
+/// `OrderBookRunTimeCallback`, in the same order within a message stack.
+/// `ProductAdded`, `TradingStatus` and `EquilibriumPrice` come first in their message stack.
+#[derive(Clone)]
+pub enum BookEvent {
+    /// `R` tag
+    ProductAdded {
+        timestamp: NaiveDateTime,
+        order_book_ids: Vec<i64>,
+        books: BookViews,
+    },
+    /// `O` tag
+    TradingStatus {
+        timestamp: NaiveDateTime,
+        msgs: Vec<TradingStatusInfo>,
+        books: BookViews,
+    },
+    /// `Z` tag
+    EquilibriumPrice {
+        timestamp: NaiveDateTime,
+        msgs: Vec<EquilibriumPrice>,
+        books: BookViews,
+    },
+    /// `T` tag
+    SecondMessage {
+        timestamp: NaiveDateTime,
+        msgs: Vec<SecondTag>,
+    },
+    Created {
+        timestamp: NaiveDateTime,
+        created: Created,
+        books: BookViews,
+    },
+    Executions {
+        timestamp: NaiveDateTime,
+        executions: Vec<OrderExecution>,
+        books: BookViews,
+    },
+    CTagExecution {
+        timestamp: NaiveDateTime,
+        executed_with_price_info: Vec<CTagWithCorrespondingPTag>,
+        books: BookViews,
+    },
+    LegPrints {
+        timestamp: NaiveDateTime,
+        leg_prints: Vec<LegPrice>,
+        books: BookViews,
+    },
+    Trades {
+        timestamp: NaiveDateTime,
+        trades: Vec<Trade>,
+        books: BookViews,
+    },
+    Deletions {
+        timestamp: NaiveDateTime,
+        deletion: Vec<OrderDeletion>,
+        books: BookViews,
+    },
+    Modified {
+        timestamp: NaiveDateTime,
+        modified_orders: Vec<ModifiedOrder>,
+        books: BookViews,
+    },
+    /// order books of the `A`, `D`, `E` and `C` tags of the message stack
+    Changes {
+        timestamp: NaiveDateTime,
+        changes: HashSet<i64>,
+        books: BookViews,
+    },
+}
+
+impl BookEvent {
+    pub fn timestamp(&self) -> &NaiveDateTime {
+        match self {
+            BookEvent::ProductAdded { timestamp, .. }
+            | BookEvent::TradingStatus { timestamp, .. }
+            | BookEvent::EquilibriumPrice { timestamp, .. }
+            | BookEvent::SecondMessage { timestamp, .. }
+            | BookEvent::Created { timestamp, .. }
+            | BookEvent::Executions { timestamp, .. }
+            | BookEvent::CTagExecution { timestamp, .. }
+            | BookEvent::LegPrints { timestamp, .. }
+            | BookEvent::Trades { timestamp, .. }
+            | BookEvent::Deletions { timestamp, .. }
+            | BookEvent::Modified { timestamp, .. }
+            | BookEvent::Changes { timestamp, .. } => timestamp,
+        }
+    }
+
+    /// None for `SecondMessage`
+    pub fn books(&self) -> Option<&BookViews> {
+        match self {
+            BookEvent::SecondMessage { .. } => None,
+            BookEvent::ProductAdded { books, .. }
+            | BookEvent::TradingStatus { books, .. }
+            | BookEvent::EquilibriumPrice { books, .. }
+            | BookEvent::Created { books, .. }
+            | BookEvent::Executions { books, .. }
+            | BookEvent::CTagExecution { books, .. }
+            | BookEvent::LegPrints { books, .. }
+            | BookEvent::Trades { books, .. }
+            | BookEvent::Deletions { books, .. }
+            | BookEvent::Modified { books, .. }
+            | BookEvent::Changes { books, .. } => Some(books),
+        }
+    }
+}
+
+/// turns the callbacks of a message stack into events
+#[derive(Default)]
+pub(crate) struct EventCollector {
+    events: VecDeque<BookEvent>,
+    /// events of the current message stack
+    pending: Vec<BookEvent>,
+    /// snapshots of the current message stack
+    snapshots: BookViews,
+}
+
+impl EventCollector {
+    /// next event of the message stacks processed so far
+    pub(crate) fn pop(&mut self) -> Option<BookEvent> {
+        self.events.pop_front()
+    }
+
+    fn views(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        order_book_ids: impl IntoIterator<Item = i64>,
+    ) -> BookViews {
+        let mut views = HashMap::new();
+        for id in order_book_ids {
+            if views.contains_key(&id) {
+                continue;
+            }
+            let Some(book) = order_book_map.get(&id) else {
+                continue;
+            };
+            let view = self
+                .snapshots
+                .entry(id)
+                .or_insert_with(|| Arc::new(BookView::new(book)));
+            views.insert(id, view.clone());
+        }
+        views
+    }
+}
+
+impl OrderBookRunTimeCallback for EventCollector {
+    fn event_start(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        _stack: &[MessageEnum],
+    ) {
+        self.snapshots.clear();
+    }
+
+    fn event_end(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        let mut products = vec![];
+        let mut statuses = vec![];
+        let mut equilibrium_prices = vec![];
+        for msg in stack.iter() {
+            match msg {
+                MessageEnum::ProductInfo(m) => products.push(m.order_book_id),
+                MessageEnum::TradingStatusInfo(m) => statuses.push((**m).clone()),
+                MessageEnum::EquilibriumPrice(m) => equilibrium_prices.push((**m).clone()),
+                _ => (),
+            }
+        }
+
+        if !products.is_empty() {
+            let books = self.views(order_book_map, products.iter().copied());
+            self.events.push_back(BookEvent::ProductAdded {
+                timestamp: *timestamp,
+                order_book_ids: products,
+                books,
+            });
+        }
+        if !statuses.is_empty() {
+            let books = self.views(order_book_map, statuses.iter().map(|m| m.order_book_id));
+            self.events.push_back(BookEvent::TradingStatus {
+                timestamp: *timestamp,
+                msgs: statuses,
+                books,
+            });
+        }
+        if !equilibrium_prices.is_empty() {
+            let books = self.views(
+                order_book_map,
+                equilibrium_prices.iter().map(|m| m.order_book_id),
+            );
+            self.events.push_back(BookEvent::EquilibriumPrice {
+                timestamp: *timestamp,
+                msgs: equilibrium_prices,
+                books,
+            });
+        }
+        self.events.extend(self.pending.drain(..));
+    }
+
+    fn second_message(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        second_messages: &[SecondTag],
+    ) {
+        self.pending.push(BookEvent::SecondMessage {
+            timestamp: *timestamp,
+            msgs: second_messages.to_vec(),
+        });
+    }
+
+    fn created(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        created: Created,
+    ) {
+        let books = self.views(order_book_map, created.msgs.iter().map(|m| m.order_book_id));
+        self.pending.push(BookEvent::Created {
+            timestamp: *timestamp,
+            created,
+            books,
+        });
+    }
+
+    fn executions(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        executions: Vec<OrderExecution>,
+    ) {
+        let books = self.views(
+            order_book_map,
+            executions.iter().map(|e| e.msg.order_book_id),
+        );
+        self.pending.push(BookEvent::Executions {
+            timestamp: *timestamp,
+            executions,
+            books,
+        });
+    }
+
+    fn ctag_execution(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        executed_with_price_info: Vec<CTagWithCorrespondingPTag>,
+    ) {
+        let ids = executed_with_price_info.iter().flat_map(|i| {
+            i.c_tag
+                .iter()
+                .map(|c| c.order_book_id)
+                .chain(i.p_tags.iter().map(|p| p.order_book_id))
+        });
+        let books = self.views(order_book_map, ids);
+        self.pending.push(BookEvent::CTagExecution {
+            timestamp: *timestamp,
+            executed_with_price_info,
+            books,
+        });
+    }
+
+    fn leg_prints(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        leg_prints: Vec<LegPrice>,
+    ) {
+        let books = self.views(order_book_map, leg_prints.iter().map(|p| p.order_book_id));
+        self.pending.push(BookEvent::LegPrints {
+            timestamp: *timestamp,
+            leg_prints,
+            books,
+        });
+    }
+
+    fn trades(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        trades: Vec<Trade>,
+    ) {
+        let books = self.views(order_book_map, trades.iter().map(|t| t.order_book_id));
+        self.pending.push(BookEvent::Trades {
+            timestamp: *timestamp,
+            trades,
+            books,
+        });
+    }
+
+    fn deletions(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        deletion: Vec<OrderDeletion>,
+    ) {
+        let books = self.views(order_book_map, deletion.iter().map(|d| d.msg.order_book_id));
+        self.pending.push(BookEvent::Deletions {
+            timestamp: *timestamp,
+            deletion,
+            books,
+        });
+    }
+
+    fn modified_orders(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        modified_orders: Vec<ModifiedOrder>,
+    ) {
+        let books = self.views(
+            order_book_map,
+            modified_orders.iter().map(|m| m.id.order_book_id),
+        );
+        self.pending.push(BookEvent::Modified {
+            timestamp: *timestamp,
+            modified_orders,
+            books,
+        });
+    }
+
+    fn order_book_id_with_changes(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        changes: &HashSet<i64>,
+    ) {
+        let books = self.views(order_book_map, changes.iter().copied());
+        self.pending.push(BookEvent::Changes {
+            timestamp: *timestamp,
+            changes: changes.clone(),
+            books,
+        });
+    }
+}
+
+/// Pull based alternative to `order_book_runtime`.
+///
+/// Message stacks are processed lazily, one at a time, when the events of the previous stack were consumed.
+pub struct BookEventIter<I> {
+    runtime: Runtime,
+    messages: I,
+    collector: EventCollector,
+}
+
+impl<I> BookEventIter<I>
+where
+    I: Iterator<Item = (NaiveDateTime, Vec<MessageEnum>)>,
+{
+    /// starts from the order books in `order_book_map`
+    pub fn new(order_book_map: HashMap<i64, OrderBook>, messages: I) -> Self {
+        Self {
+            runtime: Runtime::new(order_book_map),
+            messages,
+            collector: EventCollector::default(),
+        }
+    }
+
+    /// current state of the books. Already includes the message stack of the events not yet consumed.
+    pub fn order_book_map(&self) -> &HashMap<i64, OrderBook> {
+        &self.runtime.order_book_map
+    }
+
+    pub fn into_order_book_map(self) -> HashMap<i64, OrderBook> {
+        self.runtime.into_order_book_map()
+    }
+}
+
+impl<I> Iterator for BookEventIter<I>
+where
+    I: Iterator<Item = (NaiveDateTime, Vec<MessageEnum>)>,
+{
+    type Item = BookEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.collector.pop() {
+                return Some(event);
+            }
+            let (timestamp, stack) = self.messages.next()?;
+            self.runtime
+                .process_group(timestamp, stack, &mut self.collector);
+        }
+    }
+}
+
+#[test]
+fn test_book_event_iter() {
+    use crate::{
+        parse,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "E,2021-03-01T00:00:02.000000000(1614556802000000000),2,FUT_NK225M_2109(590334),S,2,100,0,,",
+        "D,2021-03-01T00:00:03.000000000(1614556803000000000),1,FUT_NK225M_2109(590334),B",
+    ];
+    let events: Vec<BookEvent> =
+        BookEventIter::new(HashMap::new(), parse(&file).into_iter()).collect();
+
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|e| {
+            match e {
+                BookEvent::ProductAdded { .. } => "product",
+                BookEvent::Created { .. } => "created",
+                BookEvent::Executions { .. } => "executions",
+                BookEvent::Trades { .. } => "trades",
+                BookEvent::Deletions { .. } => "deletions",
+                BookEvent::Changes { .. } => "changes",
+                _ => "other",
+            }
+        })
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            "product",
+            "created",
+            "changes",
+            "executions",
+            "trades",
+            "changes",
+            "deletions",
+            "changes"
+        ]
+    );
+
+    // views are taken after the stack was applied and shared within the stack
+    let (BookEvent::Executions { books: b1, .. }, BookEvent::Trades { books: b2, .. }) =
+        (&events[3], &events[4])
+    else {
+        unreachable!()
+    };
+    assert_eq!(b1[&590334].best_ask.map(|l| l.qty), Some(3));
+    assert_eq!(b1[&590334].trade_statistics.volume, 2);
+    assert!(Arc::ptr_eq(&b1[&590334], &b2[&590334]));
+    assert!(events[1].books().unwrap()[&590334].best_ask.is_some());
+
+    // pull based: only the first stacks are processed
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "D,2021-03-01T00:00:03.000000000(1614556803000000000),1,FUT_NK225M_2109(590334),B",
+    ];
+    let mut iter = BookEventIter::new(HashMap::new(), parse(&file).into_iter());
+    let created = iter
+        .by_ref()
+        .take_while(|e| !matches!(e, BookEvent::Created { .. }))
+        .count();
+    assert_eq!(created, 1);
+    assert_eq!(iter.order_book_map()[&590334].orders.len(), 1);
+}
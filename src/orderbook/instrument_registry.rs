@@ -0,0 +1,388 @@
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+    HashMap,
+};
+
+use chrono::NaiveDateTime;
+
+use crate::{
+    CombinationProduct,
+    FinancialProduct,
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    ProductInfo,
+    PutOrCall,
+    Symbol,
+};
+
+/// Index of every instrument announced by `R` tags.
+///
+/// Products can be looked up by order book id, symbol, underlying, expiry, strike and put/call
+/// without scanning the order book map. Combination legs announced by `M` tags are kept per combination order book id.
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentRegistry {
+    products: HashMap<i64, ProductInfo>,
+    /// products with symbols that could not be parsed are not in this map
+    symbols: HashMap<i64, Symbol>,
+    by_symbol: HashMap<String, i64>,
+    by_underlying: HashMap<i64, BTreeSet<i64>>,
+    by_expiration: BTreeMap<i64, BTreeSet<i64>>,
+    by_strike: BTreeMap<i64, BTreeSet<i64>>,
+    by_put_or_call: HashMap<PutOrCall, BTreeSet<i64>>,
+    combo_legs: HashMap<i64, Vec<CombinationProduct>>,
+}
+
+/// Filter used by `InstrumentRegistry::query`.
+/// Fields set to `None` are not used for filtering.
+///
+/// ```ignore
+/// // all NK225 weekly puts expiring 2021-03-05
+/// let query = InstrumentQuery {
+///     product_name: Some("NK225".to_string()),
+///     put_or_call: Some(PutOrCall::Put),
+///     expiration_date: Some(20210305),
+///     weekly: Some(true),
+///     ..Default::default()
+/// };
+/// let puts = registry.query(&query);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstrumentQuery {
+    /// product name embeded in the symbol. e.g. `NK225` for `PUT_NK225_210312_19250`
+    pub product_name: Option<String>,
+    pub financial_product: Option<FinancialProduct>,
+    pub underlying_order_book_id: Option<i64>,
+    /// expiration date in `yyyymmdd` format, same as `ProductInfo::expiration_date`
+    pub expiration_date: Option<i64>,
+    pub strike_price: Option<i64>,
+    pub put_or_call: Option<PutOrCall>,
+    /// true for weekly products. e.g. `CAL_NK225_210305W_30250`
+    pub weekly: Option<bool>,
+}
+
+impl InstrumentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// builds registry from order books created by the runtime
+    pub fn from_order_book_map(order_book_map: &HashMap<i64, OrderBook>) -> Self {
+        let mut registry = Self::new();
+        for book in order_book_map.values() {
+            registry.insert_product_info(book.product_info.clone());
+            for m in book.combination_product_info.iter() {
+                registry.insert_combination_product(m.clone());
+            }
+        }
+        registry
+    }
+
+    /// builds registry from parsed messages. Messages other than `R` and `M` tag are ignored.
+    pub fn from_messages<'a>(msgs: impl IntoIterator<Item = &'a MessageEnum>) -> Self {
+        let mut registry = Self::new();
+        for msg in msgs {
+            registry.push_message(msg);
+        }
+        registry
+    }
+
+    /// registers `R` and `M` tag. Other messages are ignored.
+    pub fn push_message(&mut self, msg: &MessageEnum) {
+        match msg {
+            MessageEnum::ProductInfo(info) => self.insert_product_info((**info).clone()),
+            MessageEnum::CombinationProduct(m) => self.insert_combination_product((**m).clone()),
+            _ => (),
+        }
+    }
+
+    /// registers a product.
+    /// `R` tag for the same order book id replaces the previous one.
+    pub fn insert_product_info(&mut self, info: ProductInfo) {
+        let id = info.order_book_id;
+        if let Some(previous) = self.products.remove(&id) {
+            self.unindex(&previous);
+        }
+
+        self.by_symbol.insert(info.symbol.clone(), id);
+        self.by_underlying
+            .entry(info.underlying_order_book_id)
+            .or_default()
+            .insert(id);
+        self.by_expiration
+            .entry(info.expiration_date)
+            .or_default()
+            .insert(id);
+        self.by_strike
+            .entry(info.strike_price)
+            .or_default()
+            .insert(id);
+        self.by_put_or_call
+            .entry(info.put_or_call)
+            .or_default()
+            .insert(id);
+        if let Ok(symbol) = info.symbol.parse() {
+            self.symbols.insert(id, symbol);
+        }
+        self.products.insert(id, info);
+    }
+
+    /// registers a leg of combination product.
+    /// The same leg is registered only once.
+    pub fn insert_combination_product(&mut self, m: CombinationProduct) {
+        let legs = self
+            .combo_legs
+            .entry(m.combination_order_book_id)
+            .or_default();
+        let check = legs.iter().any(|leg| {
+            leg.leg_order_book_id == m.leg_order_book_id
+                && leg.leg_side == m.leg_side
+                && leg.leg_ratio == m.leg_ratio
+        });
+        if !check {
+            legs.push(m);
+        }
+    }
+
+    fn unindex(&mut self, info: &ProductInfo) {
+        let id = info.order_book_id;
+        self.symbols.remove(&id);
+        if self.by_symbol.get(&info.symbol) == Some(&id) {
+            self.by_symbol.remove(&info.symbol);
+        }
+        if let Some(set) = self.by_underlying.get_mut(&info.underlying_order_book_id) {
+            set.remove(&id);
+        }
+        if let Some(set) = self.by_expiration.get_mut(&info.expiration_date) {
+            set.remove(&id);
+        }
+        if let Some(set) = self.by_strike.get_mut(&info.strike_price) {
+            set.remove(&id);
+        }
+        if let Some(set) = self.by_put_or_call.get_mut(&info.put_or_call) {
+            set.remove(&id);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.products.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.products.is_empty()
+    }
+
+    /// iterates over every registered product
+    pub fn products(&self) -> impl Iterator<Item = &ProductInfo> {
+        self.products.values()
+    }
+
+    pub fn get(&self, order_book_id: i64) -> Option<&ProductInfo> {
+        self.products.get(&order_book_id)
+    }
+
+    pub fn get_by_symbol(&self, symbol: &str) -> Option<&ProductInfo> {
+        self.by_symbol
+            .get(symbol)
+            .and_then(|id| self.products.get(id))
+    }
+
+    /// returns typed symbol of the product.
+    /// None if the product is not registered or the symbol could not be parsed.
+    pub fn symbol(&self, order_book_id: i64) -> Option<&Symbol> {
+        self.symbols.get(&order_book_id)
+    }
+
+    /// order book ids that has `underlying_order_book_id` as it's underlying
+    pub fn by_underlying(&self, underlying_order_book_id: i64) -> Vec<i64> {
+        Self::ids(self.by_underlying.get(&underlying_order_book_id))
+    }
+
+    /// order book ids that expires on `expiration_date` (`yyyymmdd`)
+    pub fn by_expiration(&self, expiration_date: i64) -> Vec<i64> {
+        Self::ids(self.by_expiration.get(&expiration_date))
+    }
+
+    pub fn by_strike(&self, strike_price: i64) -> Vec<i64> {
+        Self::ids(self.by_strike.get(&strike_price))
+    }
+
+    pub fn by_put_or_call(&self, put_or_call: PutOrCall) -> Vec<i64> {
+        Self::ids(self.by_put_or_call.get(&put_or_call))
+    }
+
+    /// list of expiration dates (`yyyymmdd`) in ascending order
+    pub fn expirations(&self) -> Vec<i64> {
+        self.by_expiration
+            .iter()
+            .filter(|(_, set)| !set.is_empty())
+            .map(|(date, _)| *date)
+            .collect()
+    }
+
+    /// legs of the combination product. Empty if `combination_order_book_id` is not a combination product.
+    pub fn combo_legs(&self, combination_order_book_id: i64) -> &[CombinationProduct] {
+        self.combo_legs
+            .get(&combination_order_book_id)
+            .map(|v| &v[..])
+            .unwrap_or(&[])
+    }
+
+    /// `ProductInfo` of the legs of the combination product, in the order `M` tags were received.
+    /// Legs that are not registered are skipped.
+    pub fn combo_leg_products(&self, combination_order_book_id: i64) -> Vec<&ProductInfo> {
+        self.combo_legs(combination_order_book_id)
+            .iter()
+            .filter_map(|leg| self.products.get(&leg.leg_order_book_id))
+            .collect()
+    }
+
+    /// combination order book ids that has `leg_order_book_id` as one of it's legs
+    pub fn combos_with_leg(&self, leg_order_book_id: i64) -> Vec<i64> {
+        let mut ids: Vec<i64> = self
+            .combo_legs
+            .iter()
+            .filter(|(_, legs)| {
+                legs.iter()
+                    .any(|leg| leg.leg_order_book_id == leg_order_book_id)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// returns products matching every field set on the query, sorted by order book id.
+    ///
+    /// Products with symbols that could not be parsed never match `product_name` and `weekly`.
+    pub fn query(&self, query: &InstrumentQuery) -> Vec<&ProductInfo> {
+        // start from the smallest index available
+        let candidates: Vec<i64> = {
+            let mut indexes = vec![];
+            if let Some(id) = query.underlying_order_book_id {
+                indexes.push(self.by_underlying.get(&id));
+            }
+            if let Some(date) = query.expiration_date {
+                indexes.push(self.by_expiration.get(&date));
+            }
+            if let Some(strike) = query.strike_price {
+                indexes.push(self.by_strike.get(&strike));
+            }
+            if let Some(poc) = query.put_or_call {
+                indexes.push(self.by_put_or_call.get(&poc));
+            }
+
+            if indexes.is_empty() {
+                let mut ids: Vec<i64> = self.products.keys().copied().collect();
+                ids.sort_unstable();
+                ids
+            } else {
+                indexes
+                    .into_iter()
+                    .min_by_key(|set| set.map(|s| s.len()).unwrap_or(0))
+                    .flatten()
+                    .map(|set| set.iter().copied().collect())
+                    .unwrap_or_default()
+            }
+        };
+
+        candidates
+            .into_iter()
+            .filter_map(|id| self.products.get(&id))
+            .filter(|info| query.matches(info, self.symbols.get(&info.order_book_id)))
+            .collect()
+    }
+
+    fn ids(set: Option<&BTreeSet<i64>>) -> Vec<i64> {
+        set.map(|s| s.iter().copied().collect()).unwrap_or_default()
+    }
+}
+
+impl InstrumentQuery {
+    /// returns true if every field set on the query matches
+    pub fn matches(&self, info: &ProductInfo, symbol: Option<&Symbol>) -> bool {
+        fn check<T: PartialEq>(opts: &Option<T>, val: &T) -> bool {
+            opts.as_ref().map(|v| v == val).unwrap_or(true)
+        }
+
+        let symbol_check = match symbol {
+            Some(symbol) => {
+                check(&self.product_name, &symbol.product_name)
+                    && check(&self.weekly, &symbol.weekly)
+            }
+            None => self.product_name.is_none() && self.weekly.is_none(),
+        };
+
+        symbol_check
+            && check(&self.financial_product, &info.financial_product)
+            && check(
+                &self.underlying_order_book_id,
+                &info.underlying_order_book_id,
+            )
+            && check(&self.expiration_date, &info.expiration_date)
+            && check(&self.strike_price, &info.strike_price)
+            && check(&self.put_or_call, &info.put_or_call)
+    }
+}
+
+impl OrderBookRunTimeCallback for InstrumentRegistry {
+    fn event_start(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        for msg in stack {
+            self.push_message(msg);
+        }
+    }
+}
+
+#[test]
+fn test_instrument_registry() {
+    let list = [
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),273351156,PUT_NK225_210305W_28375,136478320,136478320,1,JPY,4,0,0,1,0,0,0,500,28375,20210305,0,2",
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),273351157,PUT_NK225_210305W_28500,136478320,136478320,1,JPY,4,0,0,1,0,0,0,500,28500,20210305,0,2",
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),273351158,CAL_NK225_210305W_28500,136478320,136478320,1,JPY,4,0,0,1,0,0,0,500,28500,20210305,0,1",
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),49283572,PUT_NK225_210312_17500,136037518,136037518,1,JPY,4,0,0,1,0,0,0,500,17500,20210312,0,2",
+        "R,2021-03-30T21:14:49.816929242(1617138889816929242),590334,FUT_NK225M_2109,166090019,166090019,3,JPY,4,0,0,1,0,0,0,510,0,20210910,0,0",
+    ];
+    let msgs: Vec<MessageEnum> = list
+        .iter()
+        .map(|s| MessageEnum::try_from(s.to_string()).unwrap())
+        .collect();
+    let registry = InstrumentRegistry::from_messages(msgs.iter());
+    assert_eq!(registry.len(), 5);
+
+    let query = InstrumentQuery {
+        product_name: Some("NK225".to_string()),
+        put_or_call: Some(PutOrCall::Put),
+        expiration_date: Some(20210305),
+        weekly: Some(true),
+        ..Default::default()
+    };
+    let ids: Vec<i64> = registry
+        .query(&query)
+        .iter()
+        .map(|i| i.order_book_id)
+        .collect();
+    assert_eq!(ids, vec![273351156, 273351157]);
+
+    assert_eq!(registry.by_underlying(510), vec![590334]);
+    assert_eq!(registry.by_strike(28500), vec![273351157, 273351158]);
+    assert_eq!(
+        registry
+            .get_by_symbol("FUT_NK225M_2109")
+            .map(|i| i.order_book_id),
+        Some(590334)
+    );
+    assert_eq!(registry.expirations(), vec![20210305, 20210312, 20210910]);
+    assert_eq!(
+        registry
+            .symbol(273351158)
+            .map(|s| (s.strike_price, s.weekly)),
+        Some((28500, true))
+    );
+    assert!(registry.combo_legs(590334).is_empty());
+}
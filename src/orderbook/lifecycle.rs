@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::io::{
+    self,
+    Write,
+};
+
+use chrono::{
+    Duration,
+    NaiveDateTime,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::callback_datatype::{
+    CTagWithCorrespondingPTag,
+    Created,
+    ModifiedOrder,
+    ModifyType,
+    OrderDeletion,
+    OrderExecution,
+};
+use crate::{
+    AddOrder,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    UniqueId,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OrderOutcome {
+    /// still on the order book
+    Resting,
+    /// fully executed
+    Filled,
+    /// removed by `D` tag
+    Cancelled,
+    /// still on the order book when the run ended, and the order book was closed.
+    /// See `OrderLifecycleTracker::expired_states`
+    Expired,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderModification {
+    pub timestamp: NaiveDateTime,
+    pub modify_type: ModifyType,
+    pub previous_price: i64,
+    pub previous_quantity: i64,
+    pub price: i64,
+    pub quantity: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderFill {
+    pub timestamp: NaiveDateTime,
+    pub price: i64,
+    pub quantity: i64,
+    pub match_id: String,
+    /// quantity left on the order book after the fill
+    pub remaining_quantity: i64,
+    pub occurred_at_cross: bool,
+}
+
+/// history of a single order
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderLifecycle {
+    pub id: UniqueId,
+    pub created_at: NaiveDateTime,
+    pub initial_price: i64,
+    pub initial_quantity: i64,
+    /// current price
+    pub price: i64,
+    /// quantity left on the order book
+    pub remaining_quantity: i64,
+    pub modifications: Vec<OrderModification>,
+    pub fills: Vec<OrderFill>,
+    pub outcome: OrderOutcome,
+    /// when the order was filled or cancelled. None while resting or expired.
+    pub ended_at: Option<NaiveDateTime>,
+}
+
+impl OrderLifecycle {
+    fn new(add: &AddOrder) -> Self {
+        Self {
+            id: UniqueId::from_add_order(add),
+            created_at: add.timestamp,
+            initial_price: add.price,
+            initial_quantity: add.quantity,
+            price: add.price,
+            remaining_quantity: add.quantity,
+            modifications: vec![],
+            fills: vec![],
+            outcome: OrderOutcome::Resting,
+            ended_at: None,
+        }
+    }
+
+    pub fn filled_quantity(&self) -> i64 {
+        self.fills.iter().map(|f| f.quantity).sum()
+    }
+
+    pub fn time_to_first_fill(&self) -> Option<Duration> {
+        self.fills.first().map(|f| f.timestamp - self.created_at)
+    }
+
+    /// None if the order did not end
+    pub fn lifetime(&self) -> Option<Duration> {
+        self.ended_at.map(|t| t - self.created_at)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.outcome != OrderOutcome::Resting
+    }
+
+    fn fill(&mut self, fill: OrderFill) {
+        self.remaining_quantity = fill.remaining_quantity;
+        if fill.remaining_quantity == 0 {
+            self.outcome = OrderOutcome::Filled;
+            self.ended_at = Some(fill.timestamp);
+        }
+        self.fills.push(fill);
+    }
+}
+
+/// Records the history of every order, keyed by `UniqueId`.
+///
+/// When an order id is reused after the order ended, the previous history is replaced.
+/// Orders created before the tracker was attached are not tracked.
+#[derive(Debug, Clone)]
+pub struct OrderLifecycleTracker {
+    orders: HashMap<UniqueId, OrderLifecycle>,
+    /// orders still resting at the end are `Expired` when the last trading status of the order book
+    /// contains one of these. `["CLOSE"]` by default.
+    pub expired_states: Vec<String>,
+}
+
+impl Default for OrderLifecycleTracker {
+    fn default() -> Self {
+        Self {
+            orders: HashMap::new(),
+            expired_states: vec!["CLOSE".to_string()],
+        }
+    }
+}
+
+impl OrderLifecycleTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    pub fn get(&self, id: &UniqueId) -> Option<&OrderLifecycle> {
+        self.orders.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &OrderLifecycle> {
+        self.orders.values()
+    }
+
+    pub fn with_outcome(&self, outcome: OrderOutcome) -> impl Iterator<Item = &OrderLifecycle> {
+        self.iter().filter(move |o| o.outcome == outcome)
+    }
+
+    pub fn for_order_book(&self, order_book_id: i64) -> impl Iterator<Item = &OrderLifecycle> {
+        self.iter()
+            .filter(move |o| o.id.order_book_id == order_book_id)
+    }
+
+    /// lifecycles in order of creation time, then id
+    pub fn sorted(&self) -> Vec<&OrderLifecycle> {
+        let mut v: Vec<&OrderLifecycle> = self.orders.values().collect();
+        v.sort_by_key(|o| (o.created_at, o.id));
+        v
+    }
+
+    /// writes every lifecycle as a json line, in the order of `sorted`
+    pub fn write_json_lines<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for lifecycle in self.sorted() {
+            serde_json::to_writer(&mut writer, lifecycle)?;
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+
+    fn is_expired(&self, book: &OrderBook) -> bool {
+        book.trading_status
+            .last()
+            .map(|s| {
+                self.expired_states
+                    .iter()
+                    .any(|state| s.state_name.contains(state.as_str()))
+            })
+            .unwrap_or(false)
+    }
+}
+
+impl OrderBookRunTimeCallback for OrderLifecycleTracker {
+    fn created(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        created: Created,
+    ) {
+        for add in created.msgs.iter() {
+            let lifecycle = OrderLifecycle::new(add);
+            self.orders.insert(lifecycle.id, lifecycle);
+        }
+    }
+
+    fn executions(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        executions: Vec<OrderExecution>,
+    ) {
+        for execution in executions {
+            let add = &execution.matched_order_after_execution;
+            if let Some(lifecycle) = self.orders.get_mut(&UniqueId::from_add_order(add)) {
+                lifecycle.fill(OrderFill {
+                    timestamp: execution.msg.timestamp,
+                    price: add.price,
+                    quantity: execution.msg.executed_quantity,
+                    match_id: execution.msg.match_id,
+                    remaining_quantity: add.quantity,
+                    occurred_at_cross: false,
+                });
+            }
+        }
+    }
+
+    fn ctag_execution(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        executed_with_price_info: Vec<CTagWithCorrespondingPTag>,
+    ) {
+        for group in executed_with_price_info {
+            for (c, add) in group.c_tag.iter().zip(group.matched_add_order.iter()) {
+                if let Some(lifecycle) = self.orders.get_mut(&UniqueId::from_add_order(add)) {
+                    lifecycle.fill(OrderFill {
+                        timestamp: c.timestamp,
+                        price: c.trade_price,
+                        quantity: c.executed_quantity,
+                        match_id: c.match_id.to_string(),
+                        remaining_quantity: add.quantity,
+                        occurred_at_cross: c.occurred_at_cross,
+                    });
+                }
+            }
+        }
+    }
+
+    fn deletions(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        deletion: Vec<OrderDeletion>,
+    ) {
+        for d in deletion {
+            if let Some(lifecycle) = self.orders.get_mut(&UniqueId::from_delete_order(&d.msg)) {
+                lifecycle.outcome = OrderOutcome::Cancelled;
+                lifecycle.ended_at = Some(d.msg.timestamp);
+            }
+        }
+    }
+
+    fn modified_orders(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        modified_orders: Vec<ModifiedOrder>,
+    ) {
+        for m in modified_orders {
+            if let Some(lifecycle) = self.orders.get_mut(&m.id) {
+                lifecycle.modifications.push(OrderModification {
+                    timestamp: m.modify_msg.timestamp,
+                    modify_type: m.modify_type,
+                    previous_price: m.previous_add_order.price,
+                    previous_quantity: m.previous_add_order.quantity,
+                    price: m.modify_msg.price,
+                    quantity: m.modify_msg.quantity,
+                });
+                lifecycle.price = m.modify_msg.price;
+                lifecycle.remaining_quantity = m.modify_msg.quantity;
+            }
+        }
+    }
+
+    fn all_done(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: Option<NaiveDateTime>,
+    ) {
+        let expired: Vec<UniqueId> = self
+            .orders
+            .values()
+            .filter(|o| o.outcome == OrderOutcome::Resting)
+            .filter(|o| {
+                order_book_map
+                    .get(&o.id.order_book_id)
+                    .map(|book| self.is_expired(book))
+                    .unwrap_or(false)
+            })
+            .map(|o| o.id)
+            .collect();
+        for id in expired {
+            if let Some(lifecycle) = self.orders.get_mut(&id) {
+                lifecycle.outcome = OrderOutcome::Expired;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_order_lifecycle_tracker() {
+    use crate::{
+        replay,
+        Side,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),3,FUT_NK225M_2109(590334),B,2,5,289800000,0,2",
+        "E,2021-03-01T00:00:02.000000000(1614556802000000000),1,FUT_NK225M_2109(590334),S,2,100,0,,",
+        "E,2021-03-01T00:00:03.000000000(1614556803000000000),1,FUT_NK225M_2109(590334),S,3,101,0,,",
+        "D,2021-03-01T00:00:04.000000000(1614556804000000000),2,FUT_NK225M_2109(590334),B",
+        "A,2021-03-01T00:00:04.000000000(1614556804000000000),2,FUT_NK225M_2109(590334),B,1,5,289950000,0,2",
+        "D,2021-03-01T00:00:05.000000000(1614556805000000000),3,FUT_NK225M_2109(590334),B",
+    ];
+
+    let mut tracker = OrderLifecycleTracker::new();
+    replay(&file, &mut tracker);
+
+    let id = |order_id, side| {
+        UniqueId {
+            order_book_id: 590334,
+            order_id,
+            side,
+        }
+    };
+    let filled = tracker.get(&id(1, Side::Sell)).unwrap();
+    assert_eq!(filled.outcome, OrderOutcome::Filled);
+    assert_eq!(filled.fills.len(), 2);
+    assert_eq!(filled.time_to_first_fill(), Some(Duration::seconds(1)));
+    assert_eq!(filled.lifetime(), Some(Duration::seconds(2)));
+
+    let modified = tracker.get(&id(2, Side::Buy)).unwrap();
+    assert_eq!(modified.outcome, OrderOutcome::Resting);
+    assert_eq!(
+        modified.modifications[0].modify_type,
+        ModifyType::PriceChange
+    );
+    assert_eq!(modified.price, 289950000);
+    assert_eq!(modified.lifetime(), None);
+
+    let cancelled = tracker.get(&id(3, Side::Buy)).unwrap();
+    assert_eq!(cancelled.outcome, OrderOutcome::Cancelled);
+    assert_eq!(cancelled.lifetime(), Some(Duration::seconds(4)));
+
+    let mut out = vec![];
+    tracker.write_json_lines(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap().lines().count(), 3);
+}
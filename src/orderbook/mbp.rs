@@ -0,0 +1,475 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+
+use chrono::{
+    Duration,
+    NaiveDateTime,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    interval_end,
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    ProductFilter,
+    Side,
+};
+
+/// aggregated price level
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MbpLevel {
+    pub price: i64,
+    pub qty: i64,
+    pub order_count: usize,
+}
+
+/// Market by price incremental message.
+///
+/// `level` is 0 for the best price. Messages have to be applied in order:
+/// `Insert` shifts the levels at and below `level` down, `Delete` shifts them up.
+/// A level pushed out of the depth is removed with an explicit `Delete`,
+/// so the receiver does not have to know the depth.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum MbpMessage {
+    Insert {
+        timestamp: NaiveDateTime,
+        order_book_id: i64,
+        side: Side,
+        level: usize,
+        price: i64,
+        qty: i64,
+        order_count: usize,
+    },
+    Update {
+        timestamp: NaiveDateTime,
+        order_book_id: i64,
+        side: Side,
+        level: usize,
+        price: i64,
+        qty: i64,
+        order_count: usize,
+    },
+    Delete {
+        timestamp: NaiveDateTime,
+        order_book_id: i64,
+        side: Side,
+        level: usize,
+        price: i64,
+    },
+    /// replaces every level of the order book. Levels are from the best to the worst.
+    Snapshot {
+        timestamp: NaiveDateTime,
+        order_book_id: i64,
+        bids: Vec<MbpLevel>,
+        asks: Vec<MbpLevel>,
+    },
+}
+
+impl MbpMessage {
+    pub fn order_book_id(&self) -> i64 {
+        match self {
+            MbpMessage::Insert { order_book_id, .. }
+            | MbpMessage::Update { order_book_id, .. }
+            | MbpMessage::Delete { order_book_id, .. }
+            | MbpMessage::Snapshot { order_book_id, .. } => *order_book_id,
+        }
+    }
+}
+
+/// levels of an order book up to `depth`, from the best to the worst
+pub fn mbp_levels(book: &OrderBook, side: Side, depth: usize) -> Vec<MbpLevel> {
+    book.levels(&side)
+        .take(depth)
+        .map(|(price, orders)| {
+            MbpLevel {
+                price: *price,
+                qty: orders.values().map(|add| add.quantity).sum(),
+                order_count: orders.len(),
+            }
+        })
+        .collect()
+}
+
+/// messages turning `current` into `new`. `current` is updated along.
+fn diff_levels(
+    timestamp: NaiveDateTime,
+    order_book_id: i64,
+    side: Side,
+    depth: usize,
+    current: &mut Vec<MbpLevel>,
+    new: &[MbpLevel],
+    out: &mut Vec<MbpMessage>,
+) {
+    // true if a is a better price than b
+    let is_better = |a: i64, b: i64| {
+        match side {
+            Side::Buy => a > b,
+            Side::Sell => a < b,
+        }
+    };
+    let insert = |level: usize, l: &MbpLevel| {
+        MbpMessage::Insert {
+            timestamp,
+            order_book_id,
+            side,
+            level,
+            price: l.price,
+            qty: l.qty,
+            order_count: l.order_count,
+        }
+    };
+    let delete = |level: usize, l: &MbpLevel| {
+        MbpMessage::Delete {
+            timestamp,
+            order_book_id,
+            side,
+            level,
+            price: l.price,
+        }
+    };
+
+    let mut level = 0;
+    while level < new.len() || level < current.len() {
+        match (current.get(level), new.get(level)) {
+            (Some(old), Some(l)) if old.price == l.price => {
+                if old != l {
+                    out.push(MbpMessage::Update {
+                        timestamp,
+                        order_book_id,
+                        side,
+                        level,
+                        price: l.price,
+                        qty: l.qty,
+                        order_count: l.order_count,
+                    });
+                    current[level] = *l;
+                }
+                level += 1;
+            }
+            (Some(old), Some(l)) if !is_better(l.price, old.price) => {
+                out.push(delete(level, old));
+                current.remove(level);
+            }
+            (Some(_), Some(l)) | (None, Some(l)) => {
+                out.push(insert(level, l));
+                current.insert(level, *l);
+                if current.len() > depth {
+                    let last = current.pop().unwrap();
+                    out.push(delete(depth, &last));
+                }
+                level += 1;
+            }
+            (Some(old), None) => {
+                out.push(delete(level, old));
+                current.remove(level);
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+/// Turns the order books into a market by price incremental feed, and passes the messages to `f`.
+///
+/// Messages of a message stack are passed in ascending order of order book id, bids before asks.
+/// With `snapshot_interval`, snapshots of every order book already sent are passed before the first
+/// message stack of each interval. Intervals are aligned to unix epoch (UTC).
+pub struct MbpFeed<F>
+where
+    F: FnMut(&MbpMessage),
+{
+    pub depth: usize,
+    /// positive. None sends no snapshots
+    pub snapshot_interval: Option<Duration>,
+    pub filter: ProductFilter,
+    /// order book id => (bids, asks) as sent
+    state: HashMap<i64, (Vec<MbpLevel>, Vec<MbpLevel>)>,
+    next_snapshot: Option<NaiveDateTime>,
+    buffer: Vec<MbpMessage>,
+    f: F,
+}
+
+impl<F> MbpFeed<F>
+where
+    F: FnMut(&MbpMessage),
+{
+    pub fn new(depth: usize, f: F) -> Self {
+        Self {
+            depth,
+            snapshot_interval: None,
+            filter: ProductFilter::default(),
+            state: HashMap::new(),
+            next_snapshot: None,
+            buffer: vec![],
+            f,
+        }
+    }
+
+    /// panics if `interval` is not positive
+    pub fn with_snapshot_interval(mut self, interval: Duration) -> Self {
+        assert!(
+            interval > Duration::zero(),
+            "snapshot interval must be positive: {:?}",
+            interval
+        );
+        self.snapshot_interval = Some(interval);
+        self
+    }
+
+    pub fn with_filter(mut self, filter: ProductFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// levels sent for the order book
+    pub fn levels(&self, order_book_id: i64) -> Option<(&[MbpLevel], &[MbpLevel])> {
+        self.state
+            .get(&order_book_id)
+            .map(|(bids, asks)| (&bids[..], &asks[..]))
+    }
+
+    /// snapshots of every order book sent, in ascending order of order book id
+    pub fn snapshots(&self, timestamp: NaiveDateTime) -> Vec<MbpMessage> {
+        let mut ids: Vec<&i64> = self.state.keys().collect();
+        ids.sort_unstable();
+        ids.into_iter()
+            .map(|id| {
+                let (bids, asks) = &self.state[id];
+                MbpMessage::Snapshot {
+                    timestamp,
+                    order_book_id: *id,
+                    bids: bids.clone(),
+                    asks: asks.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+impl<F> OrderBookRunTimeCallback for MbpFeed<F>
+where
+    F: FnMut(&MbpMessage),
+{
+    fn event_start(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        _stack: &[MessageEnum],
+    ) {
+        let Some(interval) = self.snapshot_interval else {
+            return;
+        };
+        if self
+            .next_snapshot
+            .map(|next| next <= *timestamp)
+            .unwrap_or(true)
+        {
+            if self.next_snapshot.is_some() {
+                for msg in self.snapshots(*timestamp) {
+                    (self.f)(&msg);
+                }
+            }
+            self.next_snapshot = interval_end(timestamp, interval);
+        }
+    }
+
+    fn order_book_id_with_changes(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        changes: &HashSet<i64>,
+    ) {
+        let mut ids: Vec<i64> = changes.iter().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let Some(book) = order_book_map.get(&id) else {
+                continue;
+            };
+            if !self.filter.matches(id, Some(book)) {
+                continue;
+            }
+            let (bids, asks) = self.state.entry(id).or_default();
+            let new_bids = mbp_levels(book, Side::Buy, self.depth);
+            let new_asks = mbp_levels(book, Side::Sell, self.depth);
+            diff_levels(
+                *timestamp,
+                id,
+                Side::Buy,
+                self.depth,
+                bids,
+                &new_bids,
+                &mut self.buffer,
+            );
+            diff_levels(
+                *timestamp,
+                id,
+                Side::Sell,
+                self.depth,
+                asks,
+                &new_asks,
+                &mut self.buffer,
+            );
+        }
+        for msg in self.buffer.drain(..) {
+            (self.f)(&msg);
+        }
+    }
+}
+
+#[test]
+fn test_diff_levels() {
+    let l = |price, qty| {
+        MbpLevel {
+            price,
+            qty,
+            order_count: 1,
+        }
+    };
+    let apply = |levels: &mut Vec<MbpLevel>, msgs: &[MbpMessage]| {
+        for msg in msgs {
+            match msg {
+                MbpMessage::Insert {
+                    level,
+                    price,
+                    qty,
+                    order_count,
+                    ..
+                } => {
+                    levels.insert(
+                        *level,
+                        MbpLevel {
+                            price: *price,
+                            qty: *qty,
+                            order_count: *order_count,
+                        },
+                    )
+                }
+                MbpMessage::Update {
+                    level,
+                    price,
+                    qty,
+                    order_count,
+                    ..
+                } => {
+                    levels[*level] = MbpLevel {
+                        price: *price,
+                        qty: *qty,
+                        order_count: *order_count,
+                    }
+                }
+                MbpMessage::Delete { level, price, .. } => {
+                    assert_eq!(levels.remove(*level).price, *price);
+                }
+                MbpMessage::Snapshot { .. } => unreachable!(),
+            }
+        }
+    };
+
+    let cases = [
+        (vec![], vec![l(100, 1), l(99, 1), l(98, 1)]),
+        (
+            vec![l(100, 1), l(99, 1), l(98, 1)],
+            vec![l(101, 1), l(100, 2), l(98, 1)],
+        ),
+        (
+            vec![l(100, 1), l(99, 1), l(98, 1)],
+            vec![l(99, 1), l(98, 1), l(97, 1)],
+        ),
+        (vec![l(100, 1), l(98, 1)], vec![l(99, 1)]),
+        (vec![l(100, 1)], vec![]),
+    ];
+    for (old, new) in cases {
+        let mut current = old.clone();
+        let mut out = vec![];
+        diff_levels(
+            Default::default(),
+            1,
+            Side::Buy,
+            3,
+            &mut current,
+            &new,
+            &mut out,
+        );
+        assert_eq!(current, new);
+        let mut received = old;
+        apply(&mut received, &out);
+        assert_eq!(received, new);
+    }
+
+    // level pushed out of the depth is deleted
+    let mut current = vec![l(100, 1), l(99, 1), l(98, 1)];
+    let mut out = vec![];
+    diff_levels(
+        Default::default(),
+        1,
+        Side::Buy,
+        3,
+        &mut current,
+        &[l(101, 1), l(100, 1), l(99, 1)],
+        &mut out,
+    );
+    assert_eq!(out.len(), 2);
+    assert!(matches!(
+        out[1],
+        MbpMessage::Delete {
+            level: 3,
+            price: 98,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_mbp_feed() {
+    use crate::{
+        replay,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S,2,5,290000000,0,2",
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),3,FUT_NK225M_2109(590334),B,1,5,289950000,0,2",
+        "E,2021-03-01T00:00:03.000000000(1614556803000000000),1,FUT_NK225M_2109(590334),S,5,100,0,,",
+        "D,2021-03-01T00:00:05.000000000(1614556805000000000),3,FUT_NK225M_2109(590334),B",
+    ];
+
+    let mut messages = vec![];
+    let mut feed = MbpFeed::new(5, |msg: &MbpMessage| messages.push(msg.clone()))
+        .with_snapshot_interval(Duration::seconds(3));
+    replay(&file, &mut feed);
+
+    let types: Vec<String> = messages
+        .iter()
+        .map(|msg| serde_json::to_value(msg).unwrap()["type"].to_string())
+        .collect();
+    assert_eq!(
+        types,
+        vec![
+            "\"Insert\"",
+            "\"Insert\"",
+            "\"Snapshot\"",
+            "\"Update\"",
+            "\"Delete\""
+        ]
+    );
+    match &messages[3] {
+        MbpMessage::Update {
+            qty, order_count, ..
+        } => assert_eq!((*qty, *order_count), (5, 1)),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+#[should_panic]
+fn test_mbp_feed_zero_snapshot_interval() {
+    let _ = MbpFeed::new(5, |_: &MbpMessage| {}).with_snapshot_interval(Duration::zero());
+}
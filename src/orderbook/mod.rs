@@ -9,8 +9,124 @@ mod runtime;
 pub use runtime::{
     order_book_runtime,
     OrderBookRunTimeCallback,
+    RuntimeStats,
 };
 
+mod dyn_runtime;
+pub use dyn_runtime::Runtime;
+
 pub mod callback_datatype;
+
+mod aggressor;
+pub use aggressor::{
+    infer_aggressor_side,
+    infer_aggressor_sides,
+};
+
+mod bbo;
+pub use bbo::{
+    BboCause,
+    BboTracker,
+    BboUpdate,
+};
+
+mod composite;
+pub(crate) use composite::{
+    impl_composite_callback,
+    Children,
+};
+
+mod combination;
+pub use combination::{
+    implied_combination_quote,
+    implied_outright_quotes,
+    ImpliedQuote,
+};
+
+mod event_iter;
+pub use event_iter::{
+    BookEvent,
+    BookEventIter,
+    BookView,
+    BookViews,
+};
+
+mod instrument_registry;
+pub use instrument_registry::{
+    InstrumentQuery,
+    InstrumentRegistry,
+};
+
+mod lifecycle;
+pub use lifecycle::{
+    OrderFill,
+    OrderLifecycle,
+    OrderLifecycleTracker,
+    OrderModification,
+    OrderOutcome,
+};
+
+mod mbp;
+pub use mbp::{
+    mbp_levels,
+    MbpFeed,
+    MbpLevel,
+    MbpMessage,
+};
+
+mod option_chain;
+pub use option_chain::{
+    OptionChain,
+    OptionChainRow,
+    OptionQuote,
+};
+
 mod parser;
 pub use parser::*;
+
+mod product_filter;
+pub use product_filter::ProductFilter;
+
+mod queue;
+pub use queue::QueuePosition;
+
+mod session;
+pub(crate) use session::interval_end;
+pub use session::{
+    Session,
+    SessionId,
+};
+
+mod signals;
+
+mod simulation;
+pub use simulation::SimulatedExecution;
+
+mod stream;
+pub use stream::{
+    BookEventStream,
+    MessageGroupStream,
+};
+
+mod tape;
+pub use tape::{
+    TapeFormat,
+    TapeRow,
+    TapeWriter,
+};
+
+#[cfg(test)]
+mod test_util;
+#[cfg(test)]
+pub(crate) use test_util::{
+    parse,
+    replay,
+    NoOp,
+    FUT_NK225M_2109,
+};
+
+mod trade_statistics;
+pub use trade_statistics::{
+    SessionOhlc,
+    TradeStatistics,
+};
@@ -0,0 +1,318 @@
+use std::collections::{
+    BTreeMap,
+    HashMap,
+    HashSet,
+};
+
+use chrono::NaiveDateTime;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    FinancialProduct,
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    PriceLevelView,
+    ProductInfo,
+    PutOrCall,
+};
+
+/// Options on a single underlying, grouped by expiration date and strike price.
+///
+/// Quotes are refreshed when used as `OrderBookRunTimeCallback`, or by calling `refresh`.
+#[derive(Debug, Clone)]
+pub struct OptionChain {
+    pub underlying_order_book_id: i64,
+    /// expiration_date (`yyyymmdd`) => strike_price => row
+    pub expiries: BTreeMap<i64, BTreeMap<i64, OptionChainRow>>,
+    /// order_book_id => (expiration_date, strike_price, put_or_call)
+    index: HashMap<i64, (i64, i64, PutOrCall)>,
+}
+
+/// call and put with the same expiration date and strike price
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct OptionChainRow {
+    pub strike_price: i64,
+    pub call: Option<OptionQuote>,
+    pub put: Option<OptionQuote>,
+}
+
+/// top of book of an option
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct OptionQuote {
+    pub order_book_id: i64,
+    pub best_bid: Option<PriceLevelView>,
+    pub best_ask: Option<PriceLevelView>,
+    /// timestamp of the last refresh. None if it was never refreshed.
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+impl OptionQuote {
+    fn new(order_book_id: i64) -> Self {
+        Self {
+            order_book_id,
+            best_bid: None,
+            best_ask: None,
+            updated_at: None,
+        }
+    }
+}
+
+impl OptionChain {
+    pub fn new(underlying_order_book_id: i64) -> Self {
+        Self {
+            underlying_order_book_id,
+            expiries: BTreeMap::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    /// builds option chain from order books that are already created.
+    /// Quotes are filled with the current state of the books.
+    pub fn from_order_book_map(
+        order_book_map: &HashMap<i64, OrderBook>,
+        underlying_order_book_id: i64,
+    ) -> Self {
+        let mut chain = Self::new(underlying_order_book_id);
+        for book in order_book_map.values() {
+            chain.insert_product(&book.product_info);
+        }
+        let ids: Vec<i64> = chain.index.keys().copied().collect();
+        for id in ids {
+            if let Some(book) = order_book_map.get(&id) {
+                chain.refresh_book(book, None);
+            }
+        }
+        chain
+    }
+
+    /// adds option to the chain.
+    /// returns false if the product is not an option on this chain's underlying.
+    pub fn insert_product(&mut self, info: &ProductInfo) -> bool {
+        if info.financial_product != FinancialProduct::Option
+            || info.underlying_order_book_id != self.underlying_order_book_id
+            || info.put_or_call.is_combo()
+        {
+            return false;
+        }
+
+        let key = (info.expiration_date, info.strike_price, info.put_or_call);
+        match self.index.insert(info.order_book_id, key) {
+            Some(previous) if previous == key => return true,
+            Some(previous) => self.remove_quote(info.order_book_id, previous),
+            None => (),
+        }
+
+        let row = self
+            .expiries
+            .entry(info.expiration_date)
+            .or_default()
+            .entry(info.strike_price)
+            .or_insert_with(|| {
+                OptionChainRow {
+                    strike_price: info.strike_price,
+                    ..Default::default()
+                }
+            });
+        let quote = OptionQuote::new(info.order_book_id);
+        match info.put_or_call {
+            PutOrCall::Call => row.call.replace(quote),
+            PutOrCall::Put => row.put.replace(quote),
+            PutOrCall::Combo => unreachable!(),
+        };
+        true
+    }
+
+    fn remove_quote(&mut self, order_book_id: i64, key: (i64, i64, PutOrCall)) {
+        let (expiration_date, strike_price, put_or_call) = key;
+        let Some(strikes) = self.expiries.get_mut(&expiration_date) else {
+            return;
+        };
+        if let Some(row) = strikes.get_mut(&strike_price) {
+            let quote = match put_or_call {
+                PutOrCall::Call => &mut row.call,
+                PutOrCall::Put => &mut row.put,
+                PutOrCall::Combo => return,
+            };
+            if quote.map(|q| q.order_book_id) == Some(order_book_id) {
+                quote.take();
+            }
+            if row.call.is_none() && row.put.is_none() {
+                strikes.remove(&strike_price);
+            }
+        }
+        if strikes.is_empty() {
+            self.expiries.remove(&expiration_date);
+        }
+    }
+
+    /// returns true if the order book is an option on this chain
+    pub fn contains(&self, order_book_id: i64) -> bool {
+        self.index.contains_key(&order_book_id)
+    }
+
+    /// expiration dates (`yyyymmdd`) in ascending order
+    pub fn expirations(&self) -> impl Iterator<Item = &i64> {
+        self.expiries.keys()
+    }
+
+    /// rows of the expiration date in ascending order of strike price
+    pub fn rows(&self, expiration_date: i64) -> impl Iterator<Item = &OptionChainRow> {
+        self.expiries
+            .get(&expiration_date)
+            .into_iter()
+            .flat_map(|strikes| strikes.values())
+    }
+
+    pub fn row(&self, expiration_date: i64, strike_price: i64) -> Option<&OptionChainRow> {
+        self.expiries
+            .get(&expiration_date)
+            .and_then(|strikes| strikes.get(&strike_price))
+    }
+
+    /// order book of the call
+    pub fn call_book<'a>(
+        &self,
+        order_book_map: &'a HashMap<i64, OrderBook>,
+        expiration_date: i64,
+        strike_price: i64,
+    ) -> Option<&'a OrderBook> {
+        self.row(expiration_date, strike_price)
+            .and_then(|row| row.call.as_ref())
+            .and_then(|quote| order_book_map.get(&quote.order_book_id))
+    }
+
+    /// order book of the put
+    pub fn put_book<'a>(
+        &self,
+        order_book_map: &'a HashMap<i64, OrderBook>,
+        expiration_date: i64,
+        strike_price: i64,
+    ) -> Option<&'a OrderBook> {
+        self.row(expiration_date, strike_price)
+            .and_then(|row| row.put.as_ref())
+            .and_then(|quote| order_book_map.get(&quote.order_book_id))
+    }
+
+    /// refreshes quotes of the order books. Order books not on this chain are ignored.
+    pub fn refresh<'a>(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        order_book_ids: impl IntoIterator<Item = &'a i64>,
+        timestamp: &NaiveDateTime,
+    ) {
+        for id in order_book_ids {
+            if let Some(book) = order_book_map.get(id) {
+                self.refresh_book(book, Some(*timestamp));
+            }
+        }
+    }
+
+    fn refresh_book(&mut self, book: &OrderBook, timestamp: Option<NaiveDateTime>) {
+        let Some((expiration_date, strike_price, put_or_call)) =
+            self.index.get(&book.order_book_id()).copied()
+        else {
+            return;
+        };
+        let row = self
+            .expiries
+            .get_mut(&expiration_date)
+            .and_then(|strikes| strikes.get_mut(&strike_price));
+        let quote = match (row, put_or_call) {
+            (Some(row), PutOrCall::Call) => row.call.as_mut(),
+            (Some(row), PutOrCall::Put) => row.put.as_mut(),
+            _ => None,
+        };
+        if let Some(quote) = quote {
+            quote.best_bid = book.best_bid();
+            quote.best_ask = book.best_ask();
+            quote.updated_at = timestamp;
+        }
+    }
+}
+
+impl OrderBookRunTimeCallback for OptionChain {
+    fn event_end(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        for msg in stack {
+            if let MessageEnum::ProductInfo(info) = msg {
+                if self.insert_product(info) {
+                    if let Some(book) = order_book_map.get(&info.order_book_id) {
+                        self.refresh_book(book, Some(*timestamp));
+                    }
+                }
+            }
+        }
+    }
+
+    fn order_book_id_with_changes(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        timestamp: &NaiveDateTime,
+        changes: &HashSet<i64>,
+    ) {
+        self.refresh(order_book_map, changes, timestamp);
+    }
+}
+
+#[test]
+fn test_option_chain() {
+    use crate::replay;
+
+    let file = [
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),273351156,PUT_NK225_210305W_28375,136478320,136478320,1,JPY,4,0,0,1,0,0,0,500,28375,20210305,0,2",
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),273351157,CAL_NK225_210305W_28375,136478320,136478320,1,JPY,4,0,0,1,0,0,0,500,28375,20210305,0,1",
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),49283572,PUT_NK225_210312_17500,136037518,136037518,1,JPY,4,0,0,1,0,0,0,501,17500,20210312,0,2",
+        "A,2021-02-28T21:07:51.000000000(1614546471000000000),1,PUT_NK225_210305W_28375(273351156),B,1,3,10000,0,2",
+        "A,2021-02-28T21:07:52.000000000(1614546472000000000),2,PUT_NK225_210305W_28375(273351156),S,1,2,20000,0,2",
+        "A,2021-02-28T21:07:53.000000000(1614546473000000000),3,CAL_NK225_210305W_28375(273351157),S,1,5,30000,0,2",
+    ];
+
+    let mut chain = OptionChain::new(500);
+    let map = replay(&file, &mut chain);
+
+    assert_eq!(
+        chain.expirations().copied().collect::<Vec<_>>(),
+        vec![20210305]
+    );
+    let row = chain.row(20210305, 28375).unwrap();
+    let put = row.put.unwrap();
+    assert_eq!(put.order_book_id, 273351156);
+    assert_eq!(
+        put.best_bid,
+        Some(PriceLevelView {
+            price: 10000,
+            qty: 3
+        })
+    );
+    assert_eq!(
+        put.best_ask,
+        Some(PriceLevelView {
+            price: 20000,
+            qty: 2
+        })
+    );
+    let call = row.call.unwrap();
+    assert_eq!(call.best_bid, None);
+    assert_eq!(
+        call.best_ask,
+        Some(PriceLevelView {
+            price: 30000,
+            qty: 5
+        })
+    );
+    assert!(!chain.contains(49283572));
+    assert_eq!(
+        chain
+            .call_book(&map, 20210305, 28375)
+            .map(|b| b.order_book_id()),
+        Some(273351157)
+    );
+}
@@ -4,6 +4,7 @@ use std::collections::{
 };
 use std::ops::RangeBounds;
 
+use chrono::NaiveDateTime;
 use serde::{
     Deserialize,
     Serialize,
@@ -19,6 +20,7 @@ use crate::{
     ProductInfo,
     Side,
     TickSize,
+    TradeStatistics,
     TradingStatusInfo,
 };
 
@@ -27,7 +29,10 @@ pub struct OrderBook {
     ///
     /// 銘柄基本情報
     pub product_info: ProductInfo,
+    /// legs of this book when it is a combination
     pub combination_product_info: Vec<CombinationProduct>,
+    /// combinations having this book as a leg
+    pub leg_of_combination_info: Vec<CombinationProduct>,
     ///
     /// tick info
     pub tick_info: Vec<TickSize>,
@@ -41,9 +46,24 @@ pub struct OrderBook {
     /// key is the price, embeded map's key is the order id of the value's put order
     /// price => {id: AddOrder}
     pub bid: PriceLevel,
+    /// time priority of the orders which kept it through a `ModifyType::ReduceQty` modification.
+    /// Other orders have the priority of their `A` tag
+    pub queue_priority: HashMap<(i64, Side), NaiveDateTime>,
 
     pub equibrium_price: Vec<EquilibriumPrice>,
     pub trading_status: Vec<TradingStatusInfo>,
+    /// updated from `E`, `C` and `P` tags by the runtime
+    pub trade_statistics: TradeStatistics,
+}
+
+fn push_combination(v: &mut Vec<CombinationProduct>, m: CombinationProduct) {
+    let known = v.iter().any(|i| {
+        i.combination_order_book_id == m.combination_order_book_id
+            && i.leg_order_book_id == m.leg_order_book_id
+    });
+    if !known {
+        v.push(m);
+    }
 }
 
 pub type PriceLevel = BTreeMap<i64, HashMap<i64, AddOrder>>;
@@ -57,7 +77,7 @@ impl OrderBook {
     fn ask_iter(&self) -> impl Iterator<Item = (&i64, &HashMap<i64, AddOrder>)> {
         self.ask
             .iter()
-            .filter(|(price, _)| price == &&(i32::MIN as i64))
+            .filter(|(price, _)| price != &&(i32::MIN as i64))
     }
 
     fn dyn_iter<'a>(
@@ -75,17 +95,26 @@ impl OrderBook {
         Self {
             product_info: r,
             combination_product_info: vec![],
+            leg_of_combination_info: vec![],
             tick_info: vec![],
             orders: HashMap::new(),
             ask: BTreeMap::new(),
             bid: BTreeMap::new(),
+            queue_priority: HashMap::new(),
             equibrium_price: vec![],
             trading_status: vec![],
+            trade_statistics: TradeStatistics::default(),
         }
     }
 
+    /// ignored if the leg is already known, e.g. when the `M` tag is sent again
     pub fn push_combination_orderbook(&mut self, m: CombinationProduct) {
-        self.combination_product_info.push(m);
+        push_combination(&mut self.combination_product_info, m);
+    }
+
+    /// ignored if the combination is already known, e.g. when the `M` tag is sent again
+    pub fn push_leg_of_combination(&mut self, m: CombinationProduct) {
+        push_combination(&mut self.leg_of_combination_info, m);
     }
 
     /// fetches a single order from OrderBook
@@ -106,6 +135,14 @@ impl OrderBook {
         None
     }
 
+    /// price levels of the side from the best to the worst
+    pub fn levels<'a>(
+        &'a self,
+        side: &Side,
+    ) -> impl Iterator<Item = (&'a i64, &'a HashMap<i64, AddOrder>)> + 'a {
+        self.dyn_iter(side)
+    }
+
     pub fn qty_at_price(&self, price: i64, side: Side) -> Option<PriceLevelView> {
         let half = match side {
             Side::Buy => &self.bid,
@@ -189,6 +226,50 @@ impl OrderBook {
         self.tick_info.push(l);
     }
 
+    /// tick size of the price from `tick_info`. The last matching `L` tag wins.
+    pub fn tick_size_at(&self, price: i64) -> Option<i64> {
+        self.tick_info
+            .iter()
+            .rev()
+            .find(|l| l.price_from <= price && price <= l.price_to)
+            .map(|l| l.tick_size)
+    }
+
+    /// number of ticks between two prices following the tick ladder of `tick_info`.
+    /// Prices don't have to be on the ladder, so the result may be fractional.
+    /// Always positive. None if a price is outside of the ladder.
+    pub fn ticks_between(&self, price_1: f64, price_2: f64) -> Option<f64> {
+        let (low, high) = if price_1 <= price_2 {
+            (price_1, price_2)
+        } else {
+            (price_2, price_1)
+        };
+        // the last `L` tag wins for the same band
+        let mut ladder: Vec<&TickSize> = self.tick_info.iter().rev().collect();
+        ladder.sort_by_key(|l| l.price_from);
+        ladder.dedup_by_key(|l| l.price_from);
+
+        let first = ladder.first()?;
+        let last = ladder.last()?;
+        if low < first.price_from as f64 || high > last.price_to as f64 {
+            return None;
+        }
+
+        let mut ticks = 0.0;
+        for (i, band) in ladder.iter().enumerate() {
+            // bands are continuous, the band ends where the next one starts
+            let end = ladder
+                .get(i + 1)
+                .map(|next| next.price_from)
+                .unwrap_or(band.price_to) as f64;
+            let overlap = high.min(end) - low.max(band.price_from as f64);
+            if overlap > 0.0 {
+                ticks += overlap / band.tick_size as f64;
+            }
+        }
+        Some(ticks)
+    }
+
     pub fn qty(&self, price: i64, side: Side) -> Option<i64> {
         let book = match side {
             Side::Buy => &self.bid,
@@ -212,6 +293,7 @@ impl OrderBook {
         };
 
         let func_d = || format!("{:?}", d);
+        let _ = self.queue_priority.remove(&(id, side));
         let price = if let Some(p) = self.orders.remove(&(id, side)) {
             p
         } else {
@@ -288,6 +370,7 @@ impl OrderBook {
 
         if check {
             let _ = self.orders.remove(&(e.order_id, e.side));
+            let _ = self.queue_priority.remove(&(e.order_id, e.side));
             let _ = level.remove(&e.order_id);
         }
 
@@ -337,6 +420,7 @@ impl OrderBook {
 
         if check {
             let _ = self.orders.remove(&(c.order_id, c.side));
+            let _ = self.queue_priority.remove(&(c.order_id, c.side));
             let _ = level.remove(&c.order_id);
         }
 
@@ -355,3 +439,39 @@ impl OrderBook {
         self.trading_status.push(s);
     }
 }
+
+#[test]
+fn test_ask_iter_skips_sentinel() {
+    use crate::{
+        replay,
+        NoOp,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),S,1,5,290050000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S,2,3,290000000,0,2",
+        // market order resting before the opening auction
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),3,FUT_NK225M_2109(590334),S,1,7,-2147483648,0,2",
+    ];
+    let map = replay(&file, &mut NoOp);
+
+    let book = &map[&590334];
+    assert_eq!(
+        book.best_ask(),
+        Some(PriceLevelView {
+            price: 290000000,
+            qty: 3
+        })
+    );
+    assert_eq!(
+        book.qty_at_depth_range(3, Side::Sell)
+            .iter()
+            .map(|l| l.price)
+            .collect::<Vec<_>>(),
+        vec![290000000, 290050000]
+    );
+    // the sentinel level is still kept in `ask`
+    assert_eq!(book.ask.len(), 3);
+}
@@ -89,7 +89,9 @@ impl JPXMBOParser {
     }
 
     fn insert_temp(&mut self, timestamp: Option<NaiveDateTime>) {
-        let timestamp = timestamp.unwrap_or(self.last_timestamp);
+        let timestamp = timestamp
+            .or_else(|| self.temp.first().map(|msg| msg.timestamp()))
+            .unwrap_or(self.last_timestamp);
         match self.map.get(&timestamp) {
             Some(index) => {
                 if let Some((_, val)) = self.itch.get_mut(*index) {
@@ -117,7 +119,8 @@ impl JPXMBOParser {
                 };
 
                 if !check {
-                    self.insert_temp(i.timestamp().into());
+                    // key is the timestamp of the messages in temp, not the incoming one
+                    self.insert_temp(None);
                     self.last_timestamp = i.timestamp();
                 };
                 self.temp.push(i);
@@ -135,3 +138,31 @@ impl JPXMBOParser {
         }
     }
 }
+
+#[test]
+fn test_group_keyed_by_its_messages_timestamp() {
+    use crate::{
+        parse,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "D,2021-03-01T00:00:03.000000000(1614556803000000000),1,FUT_NK225M_2109(590334),B",
+        // late line of an earlier stack is appended to it
+        "D,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S",
+    ];
+    let itch = parse(&file);
+
+    assert_eq!(
+        itch.iter()
+            .map(|(_, stack)| stack.len())
+            .collect::<Vec<_>>(),
+        vec![1, 3, 1]
+    );
+    for (timestamp, stack) in itch.iter() {
+        assert!(stack.iter().all(|msg| &msg.timestamp() == timestamp));
+    }
+}
@@ -0,0 +1,31 @@
+use std::collections::HashSet;
+
+use crate::{
+    FinancialProduct,
+    OrderBook,
+};
+
+/// Selects order books for `TapeWriter`, `MbpFeed`, `BarBuilder` and `OrderFlowTracker`.
+/// Fields set to `None` are not used for filtering.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProductFilter {
+    pub order_book_ids: Option<HashSet<i64>>,
+    pub financial_product: Option<FinancialProduct>,
+}
+
+impl ProductFilter {
+    /// a book unknown to the runtime only matches without `financial_product`
+    pub fn matches(&self, order_book_id: i64, book: Option<&OrderBook>) -> bool {
+        let id_check = self
+            .order_book_ids
+            .as_ref()
+            .map(|ids| ids.contains(&order_book_id))
+            .unwrap_or(true);
+        let product_check = match (self.financial_product, book) {
+            (Some(product), Some(book)) => book.product_info.financial_product == product,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        id_check && product_check
+    }
+}
@@ -0,0 +1,211 @@
+use chrono::{
+    Duration,
+    NaiveDateTime,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    AddOrder,
+    OrderBook,
+    Side,
+};
+
+/// position of a resting order within its price level
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct QueuePosition {
+    pub price: i64,
+    /// 0 for the first order in the queue
+    pub index: usize,
+    /// number of orders at the price level
+    pub order_count: usize,
+    /// quantity of the orders before this one
+    pub qty_ahead: i64,
+    /// quantity of the orders after this one
+    pub qty_behind: i64,
+}
+
+/// Queue order is approximated by the `A` tag of each order:
+/// orders are sorted by priority timestamp, then by `order_book_position`, then by order id.
+/// A modified order is re-sent as `A` tag and loses its time priority, so the time of the last
+/// modification is used for it. A `ModifyType::ReduceQty` modification keeps the time priority of
+/// the original order, kept in `OrderBook::queue_priority`.
+impl OrderBook {
+    /// orders at the price level in queue order
+    pub fn queue(&self, price: i64, side: Side) -> Vec<&AddOrder> {
+        let half = match side {
+            Side::Buy => &self.bid,
+            Side::Sell => &self.ask,
+        };
+        let mut orders: Vec<&AddOrder> = half
+            .get(&price)
+            .map(|level| level.values().collect())
+            .unwrap_or_default();
+        orders.sort_by_key(|a| (self.priority_of(a), a.order_book_position, a.order_id));
+        orders
+    }
+
+    /// timestamp giving the time priority of the order
+    pub fn priority_timestamp(&self, order_id: i64, side: Side) -> Option<NaiveDateTime> {
+        self.order(&order_id, &side).map(|a| self.priority_of(a))
+    }
+
+    /// keeps `timestamp` as time priority of the resting order.
+    /// Called by the runtime when an order is modified with `ModifyType::ReduceQty`
+    pub fn keep_priority(&mut self, order_id: i64, side: Side, timestamp: NaiveDateTime) {
+        if self.orders.contains_key(&(order_id, side)) {
+            self.queue_priority.insert((order_id, side), timestamp);
+        }
+    }
+
+    fn priority_of(&self, a: &AddOrder) -> NaiveDateTime {
+        self.queue_priority
+            .get(&(a.order_id, a.side))
+            .copied()
+            .unwrap_or(a.timestamp)
+    }
+
+    /// time since the order was put on the book (or last modified)
+    pub fn order_age(&self, order_id: i64, side: Side, now: &NaiveDateTime) -> Option<Duration> {
+        self.order(&order_id, &side).map(|a| *now - a.timestamp)
+    }
+
+    pub fn queue_position(&self, order_id: i64, side: Side) -> Option<QueuePosition> {
+        let price = *self.orders.get(&(order_id, side))?;
+        let queue = self.queue(price, side);
+        let index = queue.iter().position(|a| a.order_id == order_id)?;
+        Some(QueuePosition {
+            price,
+            index,
+            order_count: queue.len(),
+            qty_ahead: queue[..index].iter().map(|a| a.quantity).sum(),
+            qty_behind: queue[index + 1..].iter().map(|a| a.quantity).sum(),
+        })
+    }
+
+    /// ages of the orders at the price level in queue order
+    pub fn order_ages_at_price(
+        &self,
+        price: i64,
+        side: Side,
+        now: &NaiveDateTime,
+    ) -> Vec<Duration> {
+        self.queue(price, side)
+            .into_iter()
+            .map(|a| *now - a.timestamp)
+            .collect()
+    }
+
+    /// ages of the orders at the best price in queue order. Empty if the side is empty.
+    pub fn order_ages_at_best(&self, side: Side, now: &NaiveDateTime) -> Vec<Duration> {
+        let best = match side {
+            Side::Buy => self.best_bid(),
+            Side::Sell => self.best_ask(),
+        };
+        best.map(|level| self.order_ages_at_price(level.price, side, now))
+            .unwrap_or_default()
+    }
+}
+
+#[test]
+fn test_queue_position() {
+    use crate::{
+        replay,
+        NoOp,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),2,FUT_NK225M_2109(590334),B,2,3,289900000,0,2",
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),3,FUT_NK225M_2109(590334),B,3,2,289900000,0,2",
+        "A,2021-03-01T00:00:03.000000000(1614556803000000000),4,FUT_NK225M_2109(590334),B,1,1,289800000,0,2",
+    ];
+    let map = replay(&file, &mut NoOp);
+    let book = &map[&590334];
+
+    assert_eq!(
+        book.queue_position(2, Side::Buy),
+        Some(QueuePosition {
+            price: 289900000,
+            index: 1,
+            order_count: 3,
+            qty_ahead: 5,
+            qty_behind: 2,
+        })
+    );
+    assert_eq!(book.queue_position(2, Side::Sell), None);
+
+    let now = NaiveDateTime::parse_from_str("2021-03-01T00:00:10", "%Y-%m-%dT%H:%M:%S").unwrap();
+    assert_eq!(
+        book.order_age(4, Side::Buy, &now),
+        Some(Duration::seconds(7))
+    );
+    assert_eq!(
+        book.order_ages_at_best(Side::Buy, &now),
+        vec![
+            Duration::seconds(9),
+            Duration::seconds(8),
+            Duration::seconds(8)
+        ]
+    );
+    assert!(book.order_ages_at_best(Side::Sell, &now).is_empty());
+}
+
+#[test]
+fn test_reduce_qty_keeps_priority() {
+    use crate::{
+        replay,
+        NoOp,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),2,FUT_NK225M_2109(590334),B,2,3,289900000,0,2",
+        "A,2021-03-01T00:00:02.000000000(1614556802000000000),3,FUT_NK225M_2109(590334),B,1,4,289800000,0,2",
+        // reduce qty
+        "D,2021-03-01T00:00:03.000000000(1614556803000000000),1,FUT_NK225M_2109(590334),B",
+        "A,2021-03-01T00:00:03.000000000(1614556803000000000),1,FUT_NK225M_2109(590334),B,2,2,289900000,0,2",
+        // price change
+        "D,2021-03-01T00:00:04.000000000(1614556804000000000),3,FUT_NK225M_2109(590334),B",
+        "A,2021-03-01T00:00:04.000000000(1614556804000000000),3,FUT_NK225M_2109(590334),B,3,4,289900000,0,2",
+        // reduce qty again
+        "D,2021-03-01T00:00:05.000000000(1614556805000000000),1,FUT_NK225M_2109(590334),B",
+        "A,2021-03-01T00:00:05.000000000(1614556805000000000),1,FUT_NK225M_2109(590334),B,3,1,289900000,0,2",
+    ];
+    let map = replay(&file, &mut NoOp);
+    let book = &map[&590334];
+
+    let ids: Vec<i64> = book
+        .queue(289900000, Side::Buy)
+        .iter()
+        .map(|a| a.order_id)
+        .collect();
+    assert_eq!(ids, vec![1, 2, 3]);
+    assert_eq!(
+        book.queue_position(1, Side::Buy),
+        Some(QueuePosition {
+            price: 289900000,
+            index: 0,
+            order_count: 3,
+            qty_ahead: 0,
+            qty_behind: 7,
+        })
+    );
+
+    let ts = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").unwrap();
+    assert_eq!(
+        book.priority_timestamp(1, Side::Buy),
+        Some(ts("2021-03-01T00:00:01"))
+    );
+    assert_eq!(
+        book.priority_timestamp(3, Side::Buy),
+        Some(ts("2021-03-01T00:00:04"))
+    );
+    assert_eq!(book.queue_priority.len(), 1);
+}
@@ -0,0 +1,113 @@
+use chrono::{
+    DateTime,
+    Duration,
+    NaiveDate,
+    NaiveDateTime,
+    NaiveTime,
+    Timelike,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// Trading session of OSE derivatives.
+///
+/// Timestamps in the feed are UTC, sessions are decided in JST (UTC+9).
+/// Anything from 08:00 to 16:00 JST (pre-open and closing auction included) is the day session,
+/// the rest is the night session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Session {
+    Day,
+    Night,
+}
+
+impl Session {
+    pub const DAY_END: NaiveTime = match NaiveTime::from_hms_opt(16, 0, 0) {
+        Some(t) => t,
+        None => unreachable!(),
+    };
+    pub const DAY_START: NaiveTime = match NaiveTime::from_hms_opt(8, 0, 0) {
+        Some(t) => t,
+        None => unreachable!(),
+    };
+    pub const JST_OFFSET_HOURS: i64 = 9;
+
+    /// `timestamp` is UTC
+    pub fn of(timestamp: &NaiveDateTime) -> Session {
+        SessionId::from_timestamp(timestamp).session
+    }
+}
+
+/// A single session, identified by the JST date the session started on.
+///
+/// The night session starting on the evening of `date` keeps the same `date` after midnight.
+/// Note that this is not the trading date of the exchange, which would be the next business day for night session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SessionId {
+    pub date: NaiveDate,
+    pub session: Session,
+}
+
+impl SessionId {
+    /// `timestamp` is UTC
+    pub fn from_timestamp(timestamp: &NaiveDateTime) -> Self {
+        let jst = *timestamp + Duration::hours(Session::JST_OFFSET_HOURS);
+        let time = jst.time();
+        if time >= Session::DAY_START && time < Session::DAY_END {
+            SessionId {
+                date: jst.date(),
+                session: Session::Day,
+            }
+        } else if time.hour() < Session::DAY_START.hour() {
+            SessionId {
+                date: jst.date().pred_opt().unwrap_or(jst.date()),
+                session: Session::Night,
+            }
+        } else {
+            SessionId {
+                date: jst.date(),
+                session: Session::Night,
+            }
+        }
+    }
+}
+
+/// end of the interval of `duration` containing `timestamp`. Intervals are aligned to unix epoch (UTC).
+pub(crate) fn interval_end(timestamp: &NaiveDateTime, duration: Duration) -> Option<NaiveDateTime> {
+    let step = duration.num_nanoseconds().filter(|step| *step > 0)?;
+    let t = timestamp.and_utc().timestamp_nanos_opt()?;
+    let end = t - t.rem_euclid(step) + step;
+    Some(DateTime::from_timestamp_nanos(end).naive_utc())
+}
+
+#[test]
+fn test_session() {
+    let ts = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").unwrap();
+    let date = |d| NaiveDate::from_ymd_opt(2021, 3, d).unwrap();
+
+    // 09:00 JST
+    assert_eq!(
+        SessionId::from_timestamp(&ts("2021-03-01T00:00:00")),
+        SessionId {
+            date: date(1),
+            session: Session::Day
+        }
+    );
+    // 17:00 JST
+    let evening = SessionId::from_timestamp(&ts("2021-03-01T08:00:00"));
+    assert_eq!(
+        evening,
+        SessionId {
+            date: date(1),
+            session: Session::Night
+        }
+    );
+    // 02:00 JST next day is the same night session
+    assert_eq!(
+        SessionId::from_timestamp(&ts("2021-03-01T17:00:00")),
+        evening
+    );
+    assert_eq!(Session::of(&ts("2021-03-01T06:59:59")), Session::Day);
+    assert_eq!(Session::of(&ts("2021-03-01T07:00:00")), Session::Night);
+}
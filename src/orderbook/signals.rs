@@ -0,0 +1,129 @@
+use crate::{
+    OrderBook,
+    Side,
+};
+
+/// Microstructure signals computed from the current state of the book.
+///
+/// Signals needing both sides return None for a one-sided or empty book.
+/// Imbalances are in `[-1, 1]`, positive when the bid side is heavier.
+/// They are 1 (or -1) for a book with only bids (or asks), and None for an empty book.
+impl OrderBook {
+    /// (best bid + best ask) / 2
+    pub fn mid_price(&self) -> Option<f64> {
+        let (bid, ask) = (self.best_bid()?, self.best_ask()?);
+        Some((bid.price + ask.price) as f64 / 2.0)
+    }
+
+    /// best ask - best bid
+    pub fn spread(&self) -> Option<i64> {
+        Some(self.best_ask()?.price - self.best_bid()?.price)
+    }
+
+    /// spread following the tick ladder of `tick_info`. Negative when the book is crossed.
+    /// None if either price is outside of the ladder.
+    pub fn spread_in_ticks(&self) -> Option<f64> {
+        let (bid, ask) = (self.best_bid()?, self.best_ask()?);
+        let ticks = self.ticks_between(bid.price as f64, ask.price as f64)?;
+        Some(if ask.price < bid.price { -ticks } else { ticks })
+    }
+
+    /// quantity imbalance over `depth` levels on each side. The level `i` (0 for the best) is weighted by `1 / (i + 1)`.
+    pub fn book_imbalance(&self, depth: usize) -> Option<f64> {
+        let weighted = |side: Side| -> f64 {
+            self.qty_at_depth_range(depth, side)
+                .iter()
+                .enumerate()
+                .map(|(i, l)| l.qty as f64 / (i + 1) as f64)
+                .sum()
+        };
+        imbalance(weighted(Side::Buy), weighted(Side::Sell))
+    }
+
+    /// imbalance of the number of orders over `depth` levels on each side
+    pub fn order_count_imbalance(&self, depth: usize) -> Option<f64> {
+        let count = |side: Side| -> f64 {
+            self.levels(&side)
+                .take(depth)
+                .map(|(_, orders)| orders.len())
+                .sum::<usize>() as f64
+        };
+        imbalance(count(Side::Buy), count(Side::Sell))
+    }
+
+    /// mid price weighted by the quantity on the other side of the best prices.
+    /// Closer to the ask when the bid side is heavier.
+    pub fn microprice(&self) -> Option<f64> {
+        let (bid, ask) = (self.best_bid()?, self.best_ask()?);
+        let total = (bid.qty + ask.qty) as f64;
+        if total <= 0.0 {
+            return self.mid_price();
+        }
+        Some((bid.price as f64 * ask.qty as f64 + ask.price as f64 * bid.qty as f64) / total)
+    }
+
+    /// average of the volume weighted price of `depth` levels of each side
+    pub fn weighted_mid(&self, depth: usize) -> Option<f64> {
+        let vwap = |side: Side| -> Option<f64> {
+            let levels = self.qty_at_depth_range(depth, side);
+            let qty: i64 = levels.iter().map(|l| l.qty).sum();
+            if qty <= 0 {
+                return None;
+            }
+            let notional: f64 = levels.iter().map(|l| l.price as f64 * l.qty as f64).sum();
+            Some(notional / qty as f64)
+        };
+        Some((vwap(Side::Buy)? + vwap(Side::Sell)?) / 2.0)
+    }
+}
+
+fn imbalance(bid: f64, ask: f64) -> Option<f64> {
+    let total = bid + ask;
+    (total > 0.0).then(|| (bid - ask) / total)
+}
+
+#[test]
+fn test_signals() {
+    use crate::{
+        replay,
+        NoOp,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "L,2021-02-28T21:07:50.931282000(1614546470931282000),FUT_NK225M_2109(590334),50000,0,999999999",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,3,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),B,2,3,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),3,FUT_NK225M_2109(590334),B,1,4,289850000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),4,FUT_NK225M_2109(590334),S,1,2,290000000,0,2",
+    ];
+    let map = replay(&file, &mut NoOp);
+    let book = &map[&590334];
+
+    assert_eq!(book.mid_price(), Some(289950000.0));
+    assert_eq!(book.spread(), Some(100000));
+    assert_eq!(book.spread_in_ticks(), Some(2.0));
+    // (6 - 2) / 8
+    assert_eq!(book.book_imbalance(1), Some(0.5));
+    // (6 + 4 / 2 - 2) / (6 + 4 / 2 + 2)
+    assert_eq!(book.book_imbalance(2), Some(0.6));
+    // (3 - 1) / 4
+    assert_eq!(book.order_count_imbalance(2), Some(0.5));
+    // (289900000 * 2 + 290000000 * 6) / 8
+    assert_eq!(book.microprice(), Some(289975000.0));
+    // ((289900000 * 6 + 289850000 * 4) / 10 + 290000000) / 2
+    assert_eq!(book.weighted_mid(2), Some(289940000.0));
+
+    let mut one_sided = book.clone();
+    one_sided.ask.clear();
+    assert_eq!(one_sided.book_imbalance(5), Some(1.0));
+    assert_eq!(one_sided.microprice(), None);
+    assert_eq!(one_sided.spread_in_ticks(), None);
+
+    let mut empty = one_sided.clone();
+    empty.bid.clear();
+    assert_eq!(empty.book_imbalance(5), None);
+    assert_eq!(empty.order_count_imbalance(5), None);
+    assert_eq!(empty.weighted_mid(5), None);
+}
@@ -0,0 +1,164 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    OrderBook,
+    PriceLevelView,
+    Side,
+};
+
+/// result of walking the opposite side of the book with a hypothetical order
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SimulatedExecution {
+    /// side of the hypothetical order
+    pub side: Side,
+    pub requested_qty: i64,
+    /// quantity filled at each price level, from the best to the worst
+    pub fills: Vec<PriceLevelView>,
+    pub filled_qty: i64,
+    /// quantity left. For a limit order, this is what would rest on the book.
+    pub unfilled_qty: i64,
+    /// sum of price * quantity of the fills
+    pub notional: i128,
+    /// None if nothing was filled
+    pub avg_price: Option<f64>,
+    /// mid price before the order. None if the book is one sided or empty.
+    pub mid_price: Option<f64>,
+    /// distance between `avg_price` and `mid_price` in ticks, positive when worse than mid.
+    /// None if either price is None or outside of the tick ladder.
+    pub slippage_ticks: Option<f64>,
+}
+
+impl OrderBook {
+    /// walks the opposite side until `qty` is filled or the side is exhausted.
+    /// The book is not modified.
+    pub fn simulate_market_order(&self, side: Side, qty: i64) -> SimulatedExecution {
+        self.simulate(side, None, qty)
+    }
+
+    /// walks the opposite side at prices equal or better than `price` until `qty` is filled.
+    /// The book is not modified.
+    pub fn simulate_limit_order(&self, side: Side, price: i64, qty: i64) -> SimulatedExecution {
+        self.simulate(side, Some(price), qty)
+    }
+
+    fn simulate(&self, side: Side, limit: Option<i64>, qty: i64) -> SimulatedExecution {
+        let opposite = match side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        };
+        let crosses = |price: i64| {
+            match (side, limit) {
+                (_, None) => true,
+                (Side::Buy, Some(limit)) => price <= limit,
+                (Side::Sell, Some(limit)) => price >= limit,
+            }
+        };
+
+        let mut fills = vec![];
+        let mut remaining = qty.max(0);
+        for (price, orders) in self.levels(&opposite) {
+            if remaining == 0 || !crosses(*price) {
+                break;
+            }
+            let level_qty: i64 = orders.values().map(|add| add.quantity).sum();
+            let filled = level_qty.min(remaining);
+            remaining -= filled;
+            fills.push(PriceLevelView {
+                price: *price,
+                qty: filled,
+            });
+        }
+
+        let filled_qty: i64 = fills.iter().map(|l| l.qty).sum();
+        let notional: i128 = fills.iter().map(|l| l.price as i128 * l.qty as i128).sum();
+        let avg_price = (filled_qty > 0).then(|| notional as f64 / filled_qty as f64);
+        let mid_price = self.mid_price();
+        let slippage_ticks = match (avg_price, mid_price) {
+            (Some(avg), Some(mid)) => {
+                self.ticks_between(avg, mid).map(|ticks| {
+                    let worse = match side {
+                        Side::Buy => avg >= mid,
+                        Side::Sell => avg <= mid,
+                    };
+                    if worse {
+                        ticks
+                    } else {
+                        -ticks
+                    }
+                })
+            }
+            _ => None,
+        };
+
+        SimulatedExecution {
+            side,
+            requested_qty: qty,
+            fills,
+            filled_qty,
+            unfilled_qty: qty.max(0) - filled_qty,
+            notional,
+            avg_price,
+            mid_price,
+            slippage_ticks,
+        }
+    }
+}
+
+#[test]
+fn test_simulate_order() {
+    use crate::{
+        replay,
+        NoOp,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        FUT_NK225M_2109,
+        "L,2021-02-28T21:07:50.931282000(1614546470931282000),FUT_NK225M_2109(590334),50000,0,999999999",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S,1,2,290000000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),3,FUT_NK225M_2109(590334),S,1,3,290050000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),4,FUT_NK225M_2109(590334),S,1,1,290100000,0,2",
+    ];
+    let map = replay(&file, &mut NoOp);
+    let book = &map[&590334];
+
+    assert_eq!(book.tick_size_at(290000000), Some(50000));
+
+    let sim = book.simulate_market_order(Side::Buy, 4);
+    assert_eq!(
+        sim.fills,
+        vec![
+            PriceLevelView {
+                price: 290000000,
+                qty: 2
+            },
+            PriceLevelView {
+                price: 290050000,
+                qty: 2
+            }
+        ]
+    );
+    assert_eq!(sim.unfilled_qty, 0);
+    assert_eq!(sim.avg_price, Some(290025000.0));
+    assert_eq!(sim.mid_price, Some(289950000.0));
+    assert_eq!(sim.slippage_ticks, Some(1.5));
+
+    let sim = book.simulate_market_order(Side::Buy, 10);
+    assert_eq!((sim.filled_qty, sim.unfilled_qty), (6, 4));
+
+    let sim = book.simulate_limit_order(Side::Buy, 290000000, 4);
+    assert_eq!((sim.filled_qty, sim.unfilled_qty), (2, 2));
+    assert_eq!(sim.slippage_ticks, Some(1.0));
+
+    let sim = book.simulate_limit_order(Side::Sell, 290000000, 4);
+    assert!(sim.fills.is_empty());
+    assert_eq!(sim.avg_price, None);
+    assert_eq!(sim.slippage_ticks, None);
+
+    // the book is not modified
+    assert_eq!(book.best_ask().map(|l| l.qty), Some(2));
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::task::{
+    Context,
+    Poll,
+};
+
+use chrono::NaiveDateTime;
+use futures_core::Stream;
+use tokio::io::{
+    AsyncBufRead,
+    AsyncBufReadExt,
+    Lines,
+};
+
+use super::event_iter::EventCollector;
+use crate::{
+    BookEvent,
+    MessageEnum,
+    OrderBook,
+    Runtime,
+};
+
+/// lines read in one call of `poll_next` before yielding to the executor
+const LINES_PER_POLL: usize = 1024;
+
+/// Stream of message stacks parsed line by line from `reader`.
+///
+/// Unlike `JPXMBOParser`, consecutive lines with the same timestamp are grouped as they arrive,
+/// so the input is expected to be in time order. Lines which could not be parsed are kept in `unknown`.
+///
+/// A reader that is always ready, e.g. an in-memory buffer, would never return `Pending`.
+/// The stream yields to the executor after `LINES_PER_POLL` lines without a complete message stack.
+pub struct MessageGroupStream<R> {
+    lines: Lines<R>,
+    temp: Vec<MessageEnum>,
+    pub unknown: Vec<String>,
+    done: bool,
+}
+
+impl<R> MessageGroupStream<R>
+where
+    R: AsyncBufRead + Unpin,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            temp: vec![],
+            unknown: vec![],
+            done: false,
+        }
+    }
+
+    fn take_group(&mut self) -> Option<(NaiveDateTime, Vec<MessageEnum>)> {
+        let timestamp = self.temp.first()?.timestamp();
+        Some((timestamp, std::mem::take(&mut self.temp)))
+    }
+}
+
+impl<R> Stream for MessageGroupStream<R>
+where
+    R: AsyncBufRead + Unpin,
+{
+    type Item = io::Result<(NaiveDateTime, Vec<MessageEnum>)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        for _ in 0..LINES_PER_POLL {
+            match Pin::new(&mut this.lines).poll_next_line(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                Poll::Ready(Ok(None)) => {
+                    this.done = true;
+                    return Poll::Ready(this.take_group().map(Ok));
+                }
+                Poll::Ready(Ok(Some(line))) => {
+                    match MessageEnum::try_from(line) {
+                        Ok(msg) => {
+                            let group = match this.temp.first() {
+                                Some(first) if first.timestamp() != msg.timestamp() => {
+                                    this.take_group()
+                                }
+                                _ => None,
+                            };
+                            this.temp.push(msg);
+                            if let Some(group) = group {
+                                return Poll::Ready(Some(Ok(group)));
+                            }
+                        }
+                        Err(e) => this.unknown.push(e),
+                    }
+                }
+            }
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Async counterpart of `BookEventIter`.
+///
+/// A message stack is pulled from `groups` only when the events of the previous one were consumed,
+/// so a slow consumer slows down the reading. Processing a message stack does not await.
+pub struct BookEventStream<S> {
+    runtime: Runtime,
+    groups: S,
+    collector: EventCollector,
+}
+
+impl<S> BookEventStream<S>
+where
+    S: Stream<Item = io::Result<(NaiveDateTime, Vec<MessageEnum>)>> + Unpin,
+{
+    /// starts from the order books in `order_book_map`
+    pub fn new(order_book_map: HashMap<i64, OrderBook>, groups: S) -> Self {
+        Self {
+            runtime: Runtime::new(order_book_map),
+            groups,
+            collector: EventCollector::default(),
+        }
+    }
+
+    /// current state of the books. Already includes the message stack of the events not yet consumed.
+    pub fn order_book_map(&self) -> &HashMap<i64, OrderBook> {
+        &self.runtime.order_book_map
+    }
+
+    pub fn into_order_book_map(self) -> HashMap<i64, OrderBook> {
+        self.runtime.into_order_book_map()
+    }
+}
+
+impl<R> BookEventStream<MessageGroupStream<R>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    /// reads the messages from `reader`. See `MessageGroupStream`
+    pub fn from_reader(order_book_map: HashMap<i64, OrderBook>, reader: R) -> Self {
+        Self::new(order_book_map, MessageGroupStream::new(reader))
+    }
+}
+
+impl<S> Stream for BookEventStream<S>
+where
+    S: Stream<Item = io::Result<(NaiveDateTime, Vec<MessageEnum>)>> + Unpin,
+{
+    type Item = io::Result<BookEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(event) = this.collector.pop() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+            match Pin::new(&mut this.groups).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok((timestamp, stack)))) => {
+                    this.runtime
+                        .process_group(timestamp, stack, &mut this.collector);
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_book_event_stream() {
+    use std::future::poll_fn;
+
+    use crate::FUT_NK225M_2109;
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),1,FUT_NK225M_2109(590334),B,1,5,289900000,0,2",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "not a message",
+        "D,2021-03-01T00:00:03.000000000(1614556803000000000),1,FUT_NK225M_2109(590334),B",
+    ]
+    .join("\n");
+
+    let mut groups = MessageGroupStream::new(file.as_bytes());
+    let mut stacks = vec![];
+    while let Some(group) = poll_fn(|cx| Pin::new(&mut groups).poll_next(cx)).await {
+        let (timestamp, stack) = group.unwrap();
+        stacks.push((timestamp, stack.len()));
+    }
+    assert_eq!(
+        stacks.iter().map(|(_, n)| *n).collect::<Vec<_>>(),
+        vec![1, 2, 1]
+    );
+    assert_eq!(groups.unknown.len(), 1);
+
+    let mut events = BookEventStream::from_reader(HashMap::new(), file.as_bytes());
+    let mut count = 0;
+    while let Some(event) = poll_fn(|cx| Pin::new(&mut events).poll_next(cx)).await {
+        let event = event.unwrap();
+        count += 1;
+        if let BookEvent::Created { created, books, .. } = event {
+            assert_eq!(created.msgs.len(), 2);
+            assert_eq!(books[&590334].best_bid.map(|l| l.qty), Some(5));
+            // backpressure: the deletion is not read yet
+            assert_eq!(events.order_book_map()[&590334].orders.len(), 2);
+        }
+    }
+    // product, created, changes, deletions, changes
+    assert_eq!(count, 5);
+    assert_eq!(events.order_book_map()[&590334].orders.len(), 1);
+
+    // yields after LINES_PER_POLL lines
+    let file = vec!["not a message"; LINES_PER_POLL + 1].join("\n");
+    let mut groups = MessageGroupStream::new(file.as_bytes());
+    let mut cx = Context::from_waker(std::task::Waker::noop());
+    assert!(Pin::new(&mut groups).poll_next(&mut cx).is_pending());
+    assert_eq!(groups.unknown.len(), LINES_PER_POLL);
+    assert!(matches!(
+        Pin::new(&mut groups).poll_next(&mut cx),
+        Poll::Ready(None)
+    ));
+}
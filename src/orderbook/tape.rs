@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::io::{
+    self,
+    Write,
+};
+
+use chrono::NaiveDateTime;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::callback_datatype::{
+    AggressorSide,
+    Trade,
+    TradeSource,
+};
+use crate::{
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+    PriceLevelView,
+    ProductFilter,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapeFormat {
+    /// comma separated values with a header row
+    Csv,
+    /// newline delimited json
+    JsonLines,
+}
+
+/// A row of time & sales.
+/// best bid and ask are the ones right before the message stack containing the trade was processed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TapeRow {
+    pub timestamp: NaiveDateTime,
+    pub order_book_id: i64,
+    pub symbol: String,
+    pub price: i64,
+    pub quantity: i64,
+    pub aggressor_side: AggressorSide,
+    pub match_id: String,
+    pub occurred_at_cross: bool,
+    pub source: TradeSource,
+    pub best_bid: Option<PriceLevelView>,
+    pub best_ask: Option<PriceLevelView>,
+}
+
+impl TapeRow {
+    pub const CSV_HEADER: &'static str = "timestamp,order_book_id,symbol,price,quantity,aggressor_side,match_id,occurred_at_cross,source,best_bid_price,best_bid_qty,best_ask_price,best_ask_qty";
+
+    pub fn to_csv(&self) -> String {
+        fn opt(v: Option<i64>) -> String {
+            v.map(|v| v.to_string()).unwrap_or_default()
+        }
+        format!(
+            "{},{},{},{},{},{:?},{},{},{:?},{},{},{},{}",
+            self.timestamp.format("%Y-%m-%dT%H:%M:%S%.9f"),
+            self.order_book_id,
+            csv_escape(&self.symbol),
+            self.price,
+            self.quantity,
+            self.aggressor_side,
+            csv_escape(&self.match_id),
+            self.occurred_at_cross,
+            self.source,
+            opt(self.best_bid.map(|v| v.price)),
+            opt(self.best_bid.map(|v| v.qty)),
+            opt(self.best_ask.map(|v| v.price)),
+            opt(self.best_ask.map(|v| v.qty)),
+        )
+    }
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Writes every trade to `writer` as CSV or newline delimited JSON.
+///
+/// ```ignore
+/// let file = std::io::BufWriter::new(std::fs::File::create("tape.csv")?);
+/// order_book_runtime(&mut map, itch.into_iter(), &mut TapeWriter::csv(file));
+/// ```
+///
+/// The runtime is stopped on the first io error, it's kept in `error`.
+pub struct TapeWriter<W: Write> {
+    writer: W,
+    pub format: TapeFormat,
+    pub filter: ProductFilter,
+    pub error: Option<io::Error>,
+    header_written: bool,
+    /// best bid and ask before the current message stack is processed
+    bbo_before: HashMap<i64, (Option<PriceLevelView>, Option<PriceLevelView>)>,
+}
+
+impl<W: Write> TapeWriter<W> {
+    pub fn new(writer: W, format: TapeFormat) -> Self {
+        Self {
+            writer,
+            format,
+            filter: ProductFilter::default(),
+            error: None,
+            header_written: false,
+            bbo_before: HashMap::new(),
+        }
+    }
+
+    pub fn csv(writer: W) -> Self {
+        Self::new(writer, TapeFormat::Csv)
+    }
+
+    pub fn json_lines(writer: W) -> Self {
+        Self::new(writer, TapeFormat::JsonLines)
+    }
+
+    pub fn with_filter(mut self, filter: ProductFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_row(&mut self, row: &TapeRow) -> io::Result<()> {
+        match self.format {
+            TapeFormat::Csv => {
+                if !self.header_written {
+                    writeln!(self.writer, "{}", TapeRow::CSV_HEADER)?;
+                    self.header_written = true;
+                }
+                writeln!(self.writer, "{}", row.to_csv())
+            }
+            TapeFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, row)?;
+                writeln!(self.writer)
+            }
+        }
+    }
+}
+
+impl<W: Write> OrderBookRunTimeCallback for TapeWriter<W> {
+    fn stop(&mut self) -> bool {
+        self.error.is_some()
+    }
+
+    fn event_start(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        stack: &[MessageEnum],
+    ) {
+        self.bbo_before.clear();
+        for msg in stack {
+            let id = match msg {
+                MessageEnum::Executed(m) => m.order_book_id,
+                MessageEnum::ExecutionWithPriceInfo(m) => m.order_book_id,
+                MessageEnum::LegPrice(m) => m.order_book_id,
+                _ => continue,
+            };
+            if let Some(book) = order_book_map.get(&id) {
+                self.bbo_before
+                    .entry(id)
+                    .or_insert_with(|| (book.best_bid(), book.best_ask()));
+            }
+        }
+    }
+
+    fn trades(
+        &mut self,
+        order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: &NaiveDateTime,
+        trades: Vec<Trade>,
+    ) {
+        for trade in trades {
+            let book = order_book_map.get(&trade.order_book_id);
+            if self.error.is_some() || !self.filter.matches(trade.order_book_id, book) {
+                continue;
+            }
+            let (best_bid, best_ask) = self
+                .bbo_before
+                .get(&trade.order_book_id)
+                .copied()
+                .unwrap_or_default();
+            let row = TapeRow {
+                timestamp: trade.timestamp,
+                order_book_id: trade.order_book_id,
+                symbol: book
+                    .map(|b| b.product_info.symbol.clone())
+                    .unwrap_or_default(),
+                price: trade.price,
+                quantity: trade.quantity,
+                aggressor_side: trade.aggressor_side,
+                match_id: trade.match_id,
+                occurred_at_cross: trade.occurred_at_cross,
+                source: trade.source,
+                best_bid,
+                best_ask,
+            };
+            if let Err(e) = self.write_row(&row) {
+                self.error.replace(e);
+            }
+        }
+    }
+
+    fn all_done(
+        &mut self,
+        _order_book_map: &HashMap<i64, OrderBook>,
+        _timestamp: Option<NaiveDateTime>,
+    ) {
+        if let Err(e) = self.writer.flush() {
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+#[test]
+fn test_tape_writer() {
+    use crate::{
+        replay,
+        FinancialProduct,
+        FUT_NK225M_2109,
+    };
+
+    let file = [
+        "R,2021-02-28T21:07:50.931282000(1614546470931282000),301531636,PUT_NK225_210305W_29500,136478320,136478320,1,JPY,4,0,0,1,0,0,0,500,29500,20210305,0,2",
+        FUT_NK225M_2109,
+        "A,2021-02-28T21:07:51.000000000(1614546471000000000),10,PUT_NK225_210305W_29500(301531636),S,1,3,3200000,0,2",
+        "A,2021-02-28T21:07:51.000000000(1614546471000000000),11,PUT_NK225_210305W_29500(301531636),B,1,2,3100000,0,2",
+        "A,2021-02-28T21:07:51.000000000(1614546471000000000),12,FUT_NK225M_2109(590334),B,1,2,290000000,0,2",
+        "E,2021-03-01T00:06:20.042573706(1614557180042573706),10,PUT_NK225_210305W_29500(301531636),S,1,100,0,,",
+        "E,2021-03-01T00:06:21.042573706(1614557181042573706),12,FUT_NK225M_2109(590334),B,1,101,0,,",
+    ];
+
+    let filter = ProductFilter {
+        financial_product: Some(FinancialProduct::Option),
+        ..Default::default()
+    };
+    let mut writer = TapeWriter::csv(vec![]).with_filter(filter);
+    replay(&file, &mut writer);
+    assert!(writer.error.is_none());
+
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], TapeRow::CSV_HEADER);
+    assert_eq!(
+        lines[1],
+        "2021-03-01T00:06:20.042573706,301531636,PUT_NK225_210305W_29500,3200000,1,Buy,100,false,Executed,3100000,2,3200000,3"
+    );
+}
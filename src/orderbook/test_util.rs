@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+
+use crate::{
+    order_book_runtime,
+    JPXMBOParser,
+    MessageEnum,
+    OrderBook,
+    OrderBookRunTimeCallback,
+};
+
+/// `R` tag of the order book used by most tests
+pub(crate) const FUT_NK225M_2109: &str = "R,2021-02-28T21:07:50.931282000(1614546470931282000),590334,FUT_NK225M_2109,166090019,166090019,3,JPY,4,0,0,1,0,0,0,510,0,20210910,0,0";
+
+pub(crate) struct NoOp;
+impl OrderBookRunTimeCallback for NoOp {}
+
+/// message stacks of `lines`
+pub(crate) fn parse(lines: &[&str]) -> Vec<(NaiveDateTime, Vec<MessageEnum>)> {
+    JPXMBOParser::from_string(lines.join("\n"))
+        .complete_parsing()
+        .itch
+}
+
+/// runs `lines` through `order_book_runtime` from empty order books
+pub(crate) fn replay(
+    lines: &[&str],
+    callback: &mut impl OrderBookRunTimeCallback,
+) -> HashMap<i64, OrderBook> {
+    let mut order_book_map = HashMap::new();
+    order_book_runtime(&mut order_book_map, parse(lines).into_iter(), callback);
+    order_book_map
+}
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::callback_datatype::Trade;
+use crate::{
+    OrderBook,
+    SessionId,
+};
+
+/// open, high, low and close of a session
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct SessionOhlc {
+    pub session: SessionId,
+    pub open: i64,
+    pub high: i64,
+    pub low: i64,
+    pub close: i64,
+    pub volume: i64,
+}
+
+/// Trading statistics of an order book, updated by the runtime from the trades of `E`, `C` and `P` tags.
+/// See `Trade` for how trades are reconstructed.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TradeStatistics {
+    pub last_timestamp: Option<NaiveDateTime>,
+    pub last_price: Option<i64>,
+    pub last_quantity: Option<i64>,
+    /// cumulative over all sessions
+    pub volume: i64,
+    /// cumulative sum of price * quantity
+    pub turnover: i128,
+    pub trade_count: usize,
+    /// every session with trades
+    pub sessions: BTreeMap<SessionId, SessionOhlc>,
+    /// price of the last trade occurred at cross (板寄せ)
+    pub last_auction_price: Option<i64>,
+}
+
+impl TradeStatistics {
+    pub fn update(&mut self, trade: &Trade) {
+        self.last_timestamp.replace(trade.timestamp);
+        self.last_price.replace(trade.price);
+        self.last_quantity.replace(trade.quantity);
+        self.volume += trade.quantity;
+        self.turnover += trade.price as i128 * trade.quantity as i128;
+        self.trade_count += 1;
+        if trade.occurred_at_cross {
+            self.last_auction_price.replace(trade.price);
+        }
+
+        let session = SessionId::from_timestamp(&trade.timestamp);
+        self.sessions
+            .entry(session)
+            .and_modify(|ohlc| {
+                ohlc.high = ohlc.high.max(trade.price);
+                ohlc.low = ohlc.low.min(trade.price);
+                ohlc.close = trade.price;
+                ohlc.volume += trade.quantity;
+            })
+            .or_insert(SessionOhlc {
+                session,
+                open: trade.price,
+                high: trade.price,
+                low: trade.price,
+                close: trade.price,
+                volume: trade.quantity,
+            });
+    }
+
+    /// session of the last trade
+    pub fn session(&self) -> Option<&SessionOhlc> {
+        self.sessions.values().next_back()
+    }
+
+    /// turnover / volume. None without trades
+    pub fn vwap(&self) -> Option<f64> {
+        (self.volume > 0).then(|| self.turnover as f64 / self.volume as f64)
+    }
+}
+
+impl OrderBook {
+    /// updates `trade_statistics`. Called by the runtime.
+    pub fn record_trade(&mut self, trade: &Trade) {
+        self.trade_statistics.update(trade);
+    }
+}
+
+#[test]
+fn test_trade_statistics() {
+    use std::collections::HashMap;
+
+    use crate::callback_datatype::Created;
+    use crate::{
+        replay,
+        OrderBookRunTimeCallback,
+        Session,
+        FUT_NK225M_2109,
+    };
+
+    struct LastPrice(Vec<Option<i64>>, Vec<Option<i64>>);
+    impl OrderBookRunTimeCallback for LastPrice {
+        fn created(
+            &mut self,
+            order_book_map: &HashMap<i64, OrderBook>,
+            _timestamp: &NaiveDateTime,
+            _created: Created,
+        ) {
+            self.1
+                .push(order_book_map[&590334].trade_statistics.last_price);
+        }
+
+        fn trades(
+            &mut self,
+            order_book_map: &HashMap<i64, OrderBook>,
+            _timestamp: &NaiveDateTime,
+            _trades: Vec<Trade>,
+        ) {
+            self.0
+                .push(order_book_map[&590334].trade_statistics.last_price);
+        }
+    }
+
+    let file = [
+        FUT_NK225M_2109,
+        "A,2021-02-28T23:00:00.000000000(1614553200000000000),1,FUT_NK225M_2109(590334),S,1,5,290000000,0,2",
+        "A,2021-02-28T23:00:00.000000000(1614553200000000000),2,FUT_NK225M_2109(590334),S,2,5,290050000,0,2",
+        "A,2021-02-28T23:00:00.000000000(1614553200000000000),3,FUT_NK225M_2109(590334),B,1,5,290000000,0,2",
+        // opening auction
+        "C,2021-03-01T00:00:00.000000000(1614556800000000000),1,FUT_NK225M_2109(590334),S,2,100,0,,,290000000,Y,N",
+        "C,2021-03-01T00:00:00.000000000(1614556800000000000),3,FUT_NK225M_2109(590334),B,2,100,0,,,290000000,Y,Y",
+        "E,2021-03-01T00:00:01.000000000(1614556801000000000),2,FUT_NK225M_2109(590334),S,1,101,0,,",
+        "A,2021-03-01T00:00:01.000000000(1614556801000000000),4,FUT_NK225M_2109(590334),B,1,2,290000000,0,2",
+        // night session
+        "E,2021-03-01T08:00:00.000000000(1614585600000000000),2,FUT_NK225M_2109(590334),S,3,102,0,,",
+    ];
+    let mut last_price = LastPrice(vec![], vec![]);
+    let map = replay(&file, &mut last_price);
+
+    // statistics are updated before the callbacks
+    assert_eq!(
+        last_price.0,
+        vec![Some(290000000), Some(290050000), Some(290050000)]
+    );
+    assert_eq!(last_price.1, vec![None, Some(290050000)]);
+
+    let stats = &map[&590334].trade_statistics;
+    assert_eq!((stats.volume, stats.trade_count), (6, 3));
+    assert_eq!(stats.turnover, 290000000 * 2 + 290050000 * 4);
+    assert_eq!(stats.last_quantity, Some(3));
+    assert_eq!(stats.last_auction_price, Some(290000000));
+    assert_eq!(stats.sessions.len(), 2);
+    let day = stats.sessions.values().next().unwrap();
+    assert_eq!(day.session.session, Session::Day);
+    assert_eq!(
+        (day.open, day.high, day.low, day.close, day.volume),
+        (290000000, 290050000, 290000000, 290050000, 3)
+    );
+    let ohlc = stats.session().unwrap();
+    assert_eq!(ohlc.session.session, Session::Night);
+    assert_eq!((ohlc.open, ohlc.volume), (290050000, 3));
+}